@@ -1,72 +1,64 @@
 //! HTTP endpoints exposed in /api context
 use crate::{
-    error::Result,
+    backend::Environment,
+    error::{Error, Result},
     github::{current_user, orgs, GitHubUser},
-    kubernetes::Environment,
+    jsonrpc,
     types::{
-        LoggedUser, SessionConfiguration, SessionUpdateConfiguration, UserConfiguration,
-        UserUpdateConfiguration,
+        BuildConfiguration, LoggedUser, RollbackConfiguration, SessionConfiguration,
+        SessionUpdateConfiguration, UserConfiguration, UserUpdateConfiguration,
     },
     Context,
 };
-use request::FormItems;
-use rocket::response::{content, status, Redirect};
+use futures::stream::{BoxStream, Stream, StreamExt};
+use rocket::response::stream::{Event, EventStream};
+use rocket::response::Redirect;
+use rocket::serde::json::{json, Json, Value};
 use rocket::{
     catch, delete, get,
-    http::{Cookie, Cookies, SameSite, Status},
-    patch, put, Outcome, State,
+    http::{Cookie, CookieJar, Header, SameSite, Status},
+    patch, post, put, Responder, State,
 };
 use rocket::{
     http::uri::Origin,
-    request::{self, FromRequest, Request},
-};
-use rocket_contrib::{
-    json,
-    json::{Json, JsonValue},
+    request::{self, FromRequest, Outcome, Request},
 };
 use rocket_oauth2::{OAuth2, TokenResponse};
 use serde::Serialize;
-use tokio::runtime::Runtime;
 
 const COOKIE_TOKEN: &str = "token";
 
 // Extract a User from cookies
-impl<'a, 'r> FromRequest<'a, 'r> for LoggedUser {
-    type Error = String;
-
-    fn from_request(request: &'a Request<'r>) -> request::Outcome<LoggedUser, String> {
-        let engine = &request
-            .guard::<State<Context>>()
-            .map_failure(|_f| (Status::BadRequest, "Can't access state".to_string()))?
-            .manager
-            .engine;
-        let mut cookies = request.cookies();
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LoggedUser {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<LoggedUser, Error> {
+        let engine = &match request.guard::<&State<Context>>().await {
+            Outcome::Success(state) => state,
+            _ => return fail(request, Error::MissingData("state")),
+        }
+        .manager
+        .engine;
+        let cookies = request.cookies();
         if let Some(token) = cookies.get_private(COOKIE_TOKEN) {
             let token_value = token.value();
-            let runtime = Runtime::new().map_err(|_| {
-                Err((
-                    Status::ExpectationFailed,
-                    "Failed to execute async fn".to_string(),
-                ))
-            })?;
-            let gh_user = runtime.block_on(current_user(token_value)).map_err(|err| {
-                // A token is present, but can't be used to access user details
-                clear(cookies);
-                log::warn!("Error while accessing user details: {}", err);
-                Err((
-                    Status::BadRequest,
-                    format!("Can't access user details {}", err),
-                ))
-            })?;
+            let gh_user = match current_user(token_value).await {
+                Ok(gh_user) => gh_user,
+                Err(err) => {
+                    // A token is present, but can't be used to access user details
+                    clear(cookies);
+                    log::warn!("Error while accessing user details: {}", err);
+                    return fail(request, Error::GitHub(err.to_string()));
+                }
+            };
             let id = gh_user.clone().login;
-            let users = runtime.block_on(engine.clone().list_users()).map_err(|_| {
-                Err((
-                    Status::FailedDependency,
-                    "Missing users ConfigMap".to_string(),
-                ))
-            })?;
-            let organizations = runtime
-                .block_on(orgs(token_value, &gh_user))
+            let users = match engine.list_users().await {
+                Ok(users) => users,
+                Err(err) => return fail(request, err),
+            };
+            let organizations = orgs(token_value, &gh_user)
+                .await
                 .unwrap_or_default()
                 .iter()
                 .map(|org| org.clone().login)
@@ -77,193 +69,413 @@ impl<'a, 'r> FromRequest<'a, 'r> for LoggedUser {
             if !filtered || user.is_some() {
                 Outcome::Success(LoggedUser {
                     id: id.clone(),
-                    admin: user.map_or(false, |user| user.admin),
+                    admin: matches!(user, Some(user) if user.admin),
                     pool_affinity: user.and_then(|user| user.pool_affinity.clone()),
-                    can_customize_duration: user.map_or(false, |user| user.can_customize_duration),
-                    can_customize_pool_affinity: user
-                        .map_or(false, |user| user.can_customize_pool_affinity),
+                    can_customize_duration: matches!(user, Some(user) if user.can_customize_duration),
+                    can_customize_pool_affinity: matches!(user, Some(user) if user.can_customize_pool_affinity),
+                    quota: user.map(|user| user.quota.clone()).unwrap_or_default(),
                     organizations,
                 })
             } else {
-                Outcome::Failure((Status::Forbidden, "User is not whitelisted".to_string()))
+                fail(request, Error::Forbidden("user is not whitelisted"))
             }
         } else {
             // No token in cookies, anonymous call
-            Outcome::Forward(())
+            Outcome::Forward(Status::Unauthorized)
         }
     }
 }
 
-fn result_to_jsonrpc<T: Serialize>(res: Result<T>) -> JsonValue {
+// The body of an error caught by a request guard, rendered by `default_catcher`
+struct CaughtError(Option<Value>);
+
+// Fails a request guard, keeping `error` around so that it can be reported to the client
+fn fail<T>(request: &Request<'_>, error: Error) -> request::Outcome<T, Error> {
+    let status = error_status(&error);
+    let body = error_to_json(&error);
+    request.local_cache(|| CaughtError(Some(body)));
+    Outcome::Error((status, error))
+}
+
+fn error_status(error: &Error) -> Status {
+    Status::from_code(error.status()).unwrap_or(Status::InternalServerError)
+}
+
+fn error_to_json(error: &Error) -> Value {
+    json!({ "error": { "code": error.code(), "message": error.to_string() } })
+}
+
+// Reports `error` with its matching HTTP status and a body of the form `{"error": {"code", "message"}}`
+fn error_response(error: Error) -> (Status, Value) {
+    let status = error_status(&error);
+    if status.code >= 500 {
+        log::error!("{}", error);
+    }
+    (status, error_to_json(&error))
+}
+
+fn result_to_response<T: Serialize>(res: Result<T>) -> (Status, Value) {
     match res {
-        Ok(val) => json!({ "result": val }),
-        Err(err) => json!({ "error": err.to_string() }),
+        Ok(val) => (Status::Ok, json!({ "result": val })),
+        Err(err) => error_response(err),
     }
 }
 
+// Streams items, such as `SessionEvent`s, as Server-Sent Events
+fn result_to_event_stream<T: Serialize>(
+    res: Result<BoxStream<'static, T>>,
+) -> std::result::Result<EventStream<impl Stream<Item = Event>>, (Status, Value)> {
+    res.map(|events| EventStream::from(events.map(|event| Event::json(&event))))
+        .map_err(error_response)
+}
+
 #[get("/")]
-pub fn get(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.clone().get(user))
+pub async fn get(state: &State<Context>, user: LoggedUser) -> (Status, Value) {
+    result_to_response(state.manager.get(user).await)
 }
 
 #[get("/", rank = 2)]
-pub fn get_unlogged(state: State<'_, Context>) -> JsonValue {
-    result_to_jsonrpc(state.manager.get_unlogged())
+pub async fn get_unlogged(state: &State<Context>) -> (Status, Value) {
+    result_to_response(state.manager.get_unlogged().await)
+}
+
+// Template resources. Only editable by Admins.
+
+/// Creates or replaces template `id`. The body is a YAML or JSON `Template`.
+#[put("/templates/<id>", data = "<content>")]
+pub async fn update_template(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+    content: String,
+) -> (Status, Value) {
+    result_to_response(state.manager.update_template(&user, &id, &content).await)
+}
+
+/// Updates template `id`. The body is a YAML or JSON merge patch.
+#[patch("/templates/<id>", data = "<content>")]
+pub async fn patch_template(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+    content: String,
+) -> (Status, Value) {
+    result_to_response(state.manager.patch_template(&user, &id, &content).await)
+}
+
+#[delete("/templates/<id>?<force>")]
+pub async fn delete_template(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+    force: Option<bool>,
+) -> (Status, Value) {
+    result_to_response(
+        state
+            .manager
+            .delete_template(&user, &id, force.unwrap_or(false))
+            .await,
+    )
+}
+
+#[get("/templates/<id>/versions")]
+pub async fn list_template_versions(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+) -> (Status, Value) {
+    result_to_response(state.manager.list_template_versions(&user, &id).await)
+}
+
+#[post("/templates/<id>/rollback", data = "<conf>")]
+pub async fn rollback_template(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+    conf: Json<RollbackConfiguration>,
+) -> (Status, Value) {
+    result_to_response(
+        state
+            .manager
+            .rollback_template(&user, &id, conf.0.version)
+            .await,
+    )
 }
 
 // User resources. Only accessible to Admins.
 
 #[get("/users/<id>")]
-pub fn get_user(state: State<'_, Context>, user: LoggedUser, id: String) -> JsonValue {
-    result_to_jsonrpc(state.manager.get_user(&user, &id))
+pub async fn get_user(state: &State<Context>, user: LoggedUser, id: String) -> (Status, Value) {
+    result_to_response(state.manager.get_user(&user, &id).await)
 }
 
 #[get("/users")]
-pub fn list_users(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.list_users(&user))
+pub async fn list_users(state: &State<Context>, user: LoggedUser) -> (Status, Value) {
+    result_to_response(state.manager.list_users(&user).await)
 }
 
 #[put("/users/<id>", data = "<conf>")]
-pub fn create_user(
-    state: State<'_, Context>,
+pub async fn create_user(
+    state: &State<Context>,
     user: LoggedUser,
     id: String,
     conf: Json<UserConfiguration>,
-) -> JsonValue {
-    result_to_jsonrpc(state.manager.clone().create_user(&user, id, conf.0))
+) -> (Status, Value) {
+    result_to_response(state.manager.create_user(&user, id, conf.0).await)
 }
 
 #[patch("/users/<id>", data = "<conf>")]
-pub fn update_user(
-    state: State<'_, Context>,
+pub async fn update_user(
+    state: &State<Context>,
     user: LoggedUser,
     id: String,
     conf: Json<UserUpdateConfiguration>,
-) -> JsonValue {
-    result_to_jsonrpc(state.manager.clone().update_user(user, id, conf.0))
+) -> (Status, Value) {
+    result_to_response(state.manager.update_user(user, id, conf.0).await)
 }
 
 #[delete("/users/<id>")]
-pub fn delete_user(state: State<'_, Context>, user: LoggedUser, id: String) -> JsonValue {
-    result_to_jsonrpc(state.manager.clone().delete_user(&user, id))
+pub async fn delete_user(state: &State<Context>, user: LoggedUser, id: String) -> (Status, Value) {
+    result_to_response(state.manager.delete_user(&user, id).await)
 }
 
-// Current Session
+// Sessions of the current user
 
 #[get("/session")]
-pub fn get_current_session(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.get_session(&user, &user.id))
+pub async fn list_current_sessions(state: &State<Context>, user: LoggedUser) -> (Status, Value) {
+    result_to_response(state.manager.list_user_sessions(&user).await)
 }
 
 #[get("/session", rank = 2)]
-pub fn get_current_session_unlogged() -> status::Unauthorized<()> {
-    status::Unauthorized::<()>(None)
+pub fn list_current_sessions_unlogged() -> (Status, Value) {
+    error_response(Error::Unauthorized())
+}
+
+#[get("/session/events")]
+pub async fn watch_current_sessions(
+    state: &State<Context>,
+    user: LoggedUser,
+) -> std::result::Result<EventStream<impl Stream<Item = Event>>, (Status, Value)> {
+    result_to_event_stream(state.manager.watch_user_sessions(&user).await)
+}
+
+#[get("/session/events", rank = 2)]
+pub fn watch_current_sessions_unlogged() -> (Status, Value) {
+    error_response(Error::Unauthorized())
 }
 
 ///
-/// Create a new session for `LoggedUser`. A single session can exist at a time.
+/// Creates a new session for `LoggedUser`. Returns either the id of the new session, or its position in the queue when at capacity.
 ///
-/// There is a short time window where multiple concurrent calls can succeed.
-/// As this call is idempotent this won't lead to multiple session creation.
+/// Once created, the session can be accessed via `/sessions/<id>`.
 ///
-#[put("/session", data = "<conf>")]
-pub fn create_current_session(
-    state: State<'_, Context>,
+#[post("/session", data = "<conf>")]
+pub async fn create_current_session(
+    state: &State<Context>,
     user: LoggedUser,
     conf: Json<SessionConfiguration>,
-) -> JsonValue {
-    result_to_jsonrpc(state.manager.create_session(&user, &user.id, conf.0))
+) -> (Status, Value) {
+    result_to_response(state.manager.create_session(&user, conf.0).await)
+}
+
+#[post("/session", data = "<_conf>", rank = 2)]
+pub fn create_current_session_unlogged(_conf: Json<SessionConfiguration>) -> (Status, Value) {
+    error_response(Error::Unauthorized())
 }
 
-#[put("/session", data = "<_conf>", rank = 2)]
-pub fn create_current_session_unlogged(
-    _conf: Json<SessionConfiguration>,
-) -> status::Unauthorized<()> {
-    status::Unauthorized::<()>(None)
+// Queued sessions of the current user
+
+#[get("/session/queue")]
+pub async fn list_queued_sessions(state: &State<Context>, user: LoggedUser) -> (Status, Value) {
+    result_to_response(state.manager.list_queued_sessions(&user).await)
+}
+
+#[get("/session/queue", rank = 2)]
+pub fn list_queued_sessions_unlogged() -> (Status, Value) {
+    error_response(Error::Unauthorized())
 }
 
-#[patch("/session", data = "<conf>")]
-pub fn update_current_session(
-    state: State<'_, Context>,
+#[get("/session/queue/events")]
+pub async fn watch_queued_sessions(
+    state: &State<Context>,
     user: LoggedUser,
-    conf: Json<SessionUpdateConfiguration>,
-) -> JsonValue {
-    result_to_jsonrpc(state.manager.update_session(&user.id, &user, conf.0))
+) -> std::result::Result<EventStream<impl Stream<Item = Event>>, (Status, Value)> {
+    result_to_event_stream(state.manager.watch_queued_sessions(&user).await)
 }
 
-#[patch("/session", data = "<_conf>", rank = 2)]
-pub fn update_current_session_unlogged(
-    _conf: Json<SessionUpdateConfiguration>,
-) -> status::Unauthorized<()> {
-    status::Unauthorized::<()>(None)
+#[get("/session/queue/events", rank = 2)]
+pub fn watch_queued_sessions_unlogged() -> (Status, Value) {
+    error_response(Error::Unauthorized())
 }
 
-#[delete("/session")]
-pub fn delete_current_session(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.delete_session(&user, &user.id))
+#[delete("/session/queue/<id>")]
+pub async fn cancel_queued_session(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+) -> (Status, Value) {
+    result_to_response(state.manager.cancel_queued_session(&user, &id).await)
 }
 
-#[delete("/session", rank = 2)]
-pub fn delete_current_session_unlogged() -> status::Unauthorized<()> {
-    status::Unauthorized::<()>(None)
+#[delete("/session/queue/<_id>", rank = 2)]
+pub fn cancel_queued_session_unlogged(_id: String) -> (Status, Value) {
+    error_response(Error::Unauthorized())
 }
 
-// Sessions
+// Sessions. Only accessible to their owner, or to Admins.
 
 #[get("/sessions/<id>")]
-pub fn get_session(state: State<'_, Context>, user: LoggedUser, id: String) -> JsonValue {
-    result_to_jsonrpc(state.manager.get_session(&user, &id))
+pub async fn get_session(state: &State<Context>, user: LoggedUser, id: String) -> (Status, Value) {
+    result_to_response(state.manager.get_session(&user, &id).await)
 }
 
 #[get("/sessions")]
-pub fn list_sessions(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.list_sessions(&user))
+pub async fn list_sessions(state: &State<Context>, user: LoggedUser) -> (Status, Value) {
+    result_to_response(state.manager.list_sessions(&user).await)
 }
 
-#[put("/sessions/<id>", data = "<conf>")]
-pub fn create_session(
-    state: State<'_, Context>,
+#[get("/sessions/events")]
+pub async fn watch_sessions(
+    state: &State<Context>,
     user: LoggedUser,
-    id: String,
-    conf: Json<SessionConfiguration>,
-) -> JsonValue {
-    result_to_jsonrpc(state.manager.create_session(&user, &id, conf.0))
+) -> std::result::Result<EventStream<impl Stream<Item = Event>>, (Status, Value)> {
+    result_to_event_stream(state.manager.watch_sessions(&user).await)
 }
 
 #[patch("/sessions/<id>", data = "<conf>")]
-pub fn update_session(
-    state: State<'_, Context>,
+pub async fn update_session(
+    state: &State<Context>,
     user: LoggedUser,
     id: String,
     conf: Json<SessionUpdateConfiguration>,
-) -> JsonValue {
-    result_to_jsonrpc(state.manager.update_session(&id, &user, conf.0))
+) -> (Status, Value) {
+    result_to_response(state.manager.update_session(&id, &user, conf.0).await)
+}
+
+/// Reports some activity of session `id`, e.g. from its open IDE. Idle sessions are undeployed.
+#[post("/sessions/<id>/heartbeat")]
+pub async fn heartbeat_session(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+) -> (Status, Value) {
+    result_to_response(state.manager.record_activity(&user, &id).await)
 }
 
 #[delete("/sessions/<id>")]
-pub fn delete_session(state: State<'_, Context>, user: LoggedUser, id: String) -> JsonValue {
-    result_to_jsonrpc(state.manager.delete_session(&user, &id))
+pub async fn delete_session(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+) -> (Status, Value) {
+    result_to_response(state.manager.delete_session(&user, &id).await)
 }
 
 // Pools
 
 #[get("/pools/<id>")]
-pub fn get_pool(state: State<'_, Context>, user: LoggedUser, id: String) -> JsonValue {
-    result_to_jsonrpc(state.manager.get_pool(&user, &id))
+pub async fn get_pool(state: &State<Context>, user: LoggedUser, id: String) -> (Status, Value) {
+    result_to_response(state.manager.get_pool(&user, &id).await)
 }
 
 #[get("/pools")]
-pub fn list_pools(state: State<'_, Context>, user: LoggedUser) -> JsonValue {
-    result_to_jsonrpc(state.manager.list_pools(&user))
+pub async fn list_pools(state: &State<Context>, user: LoggedUser) -> (Status, Value) {
+    result_to_response(state.manager.list_pools(&user).await)
+}
+
+// Workspaces. Only accessible to their owner, or to Admins.
+
+#[get("/workspaces/<id>")]
+pub async fn get_workspace(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+) -> (Status, Value) {
+    result_to_response(state.manager.get_workspace(&user, &id).await)
+}
+
+#[get("/workspaces")]
+pub async fn list_workspaces(state: &State<Context>, user: LoggedUser) -> (Status, Value) {
+    result_to_response(state.manager.list_workspaces(&user).await)
+}
+
+/// A gzipped tarball, downloaded as an attachment
+#[derive(Responder)]
+#[response(content_type = "application/gzip")]
+pub struct Archive(Vec<u8>, Header<'static>);
+
+#[get("/workspaces/<id>/snapshot")]
+pub async fn snapshot_workspace(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+) -> std::result::Result<Archive, (Status, Value)> {
+    let disposition = format!("attachment; filename=\"workspace-{}.tar.gz\"", id);
+    state
+        .manager
+        .snapshot_workspace(&user, &id)
+        .await
+        .map(|archive| Archive(archive, Header::new("Content-Disposition", disposition)))
+        .map_err(error_response)
+}
+
+#[delete("/workspaces/<id>")]
+pub async fn delete_workspace(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+) -> (Status, Value) {
+    result_to_response(state.manager.delete_workspace(&user, &id).await)
+}
+
+// Builds. Only accessible to Admins.
+
+#[get("/builds/<id>")]
+pub async fn get_build(state: &State<Context>, user: LoggedUser, id: String) -> (Status, Value) {
+    result_to_response(state.manager.get_build(&user, &id).await)
+}
+
+#[get("/builds")]
+pub async fn list_builds(state: &State<Context>, user: LoggedUser) -> (Status, Value) {
+    result_to_response(state.manager.list_builds(&user).await)
+}
+
+#[post("/templates/<id>/builds", data = "<conf>")]
+pub async fn create_build(
+    state: &State<Context>,
+    user: LoggedUser,
+    id: String,
+    conf: Json<BuildConfiguration>,
+) -> (Status, Value) {
+    result_to_response(state.manager.create_build(&user, &id, conf.0).await)
+}
+
+// JSON-RPC
+
+#[derive(Responder)]
+pub enum RpcResponse {
+    Response(Value),
+    // Only notifications were received
+    #[response(status = 204)]
+    Empty(()),
+}
+
+/// JSON-RPC 2.0 access to all operations, see `jsonrpc`
+#[post("/rpc", data = "<body>")]
+pub async fn rpc(state: &State<Context>, user: Option<LoggedUser>, body: String) -> RpcResponse {
+    match jsonrpc::handle(&state.manager, user, &body).await {
+        Some(response) => RpcResponse::Response(response),
+        None => RpcResponse::Empty(()),
+    }
 }
 
 // GitHub login logic
 
 fn query_segment(origin: &Origin) -> String {
     origin.query().map_or("".to_string(), |query| {
-        let v: Vec<String> = FormItems::from(query)
-            .map(|i| i.key_value_decoded())
-            .filter(|(k, _)| k != "code" && k != "state")
+        let v: Vec<String> = query
+            .segments()
+            .filter(|(k, _)| *k != "code" && *k != "state")
             .map(|(k, v)| format!("{}={}", k, v))
             .collect();
         if v.is_empty() {
@@ -281,24 +493,20 @@ fn protocol(env: &Environment) -> String {
 // Gets called from UI. Then redirects to the GitHub `auth_uri` which itself redirects to `/auth/github`
 #[get("/login/github")]
 pub fn github_login(
-    state: State<'_, Context>,
+    state: &State<Context>,
     origin: &Origin,
     oauth2: OAuth2<GitHubUser>,
-    mut cookies: Cookies<'_>,
+    cookies: &CookieJar<'_>,
 ) -> Redirect {
-    let manager = state.manager.clone();
+    let manager = &state.manager;
     let redirect_uri = format!(
         "{}://{}/api/auth/github{}",
-        protocol(&manager.engine.env),
-        manager.engine.env.host,
+        protocol(manager.engine.env()),
+        manager.engine.env().host,
         query_segment(origin)
     );
     oauth2
-        .get_redirect_extras(
-            &mut cookies,
-            &["user:read"],
-            &[("redirect_uri", &redirect_uri)],
-        )
+        .get_redirect_extras(cookies, &["user:read"], &[("redirect_uri", &redirect_uri)])
         .unwrap()
 }
 
@@ -308,37 +516,47 @@ pub fn github_login(
 pub fn post_install_callback(
     origin: &Origin,
     token: TokenResponse<GitHubUser>,
-    mut cookies: Cookies<'_>,
+    cookies: &CookieJar<'_>,
 ) -> Redirect {
     cookies.add_private(
-        Cookie::build(COOKIE_TOKEN, token.access_token().to_string())
-            .same_site(SameSite::Lax)
-            .finish(),
+        Cookie::build((COOKIE_TOKEN, token.access_token().to_string())).same_site(SameSite::Lax),
     );
 
     Redirect::to(format!("/{}", query_segment(origin)))
 }
 
 #[get("/login?<bearer>")]
-pub fn login(mut cookies: Cookies<'_>, bearer: String) {
-    cookies.add_private(
-        Cookie::build(COOKIE_TOKEN, bearer)
-            .same_site(SameSite::Lax)
-            .finish(),
-    )
+pub fn login(cookies: &CookieJar<'_>, bearer: String) {
+    cookies.add_private(Cookie::build((COOKIE_TOKEN, bearer)).same_site(SameSite::Lax))
 }
 
 #[get("/logout")]
-pub fn logout(cookies: Cookies<'_>) {
+pub fn logout(cookies: &CookieJar<'_>) {
     clear(cookies)
 }
 
-fn clear(mut cookies: Cookies<'_>) {
-    cookies.remove_private(Cookie::named(COOKIE_TOKEN));
+fn clear(cookies: &CookieJar<'_>) {
+    cookies.remove_private(Cookie::from(COOKIE_TOKEN));
 }
 
-#[allow(dead_code)]
-#[catch(400)] // TODO move to catch(default) once it's available
-pub fn bad_request_catcher(_req: &Request<'_>) -> content::Html<String> {
-    content::Html("<p>Sorry something unexpected happened!</p>".to_string())
+///
+/// Reports errors as JSON. Errors raised by request guards are reported as is,
+/// others are described by their HTTP status only.
+///
+#[catch(default)]
+pub fn default_catcher(status: Status, req: &Request<'_>) -> (Status, Value) {
+    let body = req.local_cache(|| CaughtError(None)).0.clone();
+    (
+        status,
+        body.unwrap_or_else(|| {
+            let code = match status.code {
+                401 => "unauthorized",
+                403 => "forbidden",
+                404 => "not_found",
+                400 | 422 => "validation",
+                _ => "internal",
+            };
+            json!({ "error": { "code": code, "message": status.reason().unwrap_or("Unknown error") } })
+        }),
+    )
 }
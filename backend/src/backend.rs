@@ -0,0 +1,451 @@
+//! Abstraction over the infrastructure hosting sessions
+use crate::{
+    error::{Error, Result},
+    kubernetes::Engine,
+    memory::MemoryEngine,
+    types::{
+        Build, BuildConfiguration, BuildDefaults, LoggedUser, Pool, Quota, Repository, Resources,
+        Session, SessionConfiguration, SessionDefaults, SessionEvent, SessionUpdateConfiguration,
+        Template, TemplateVersion, User, UserConfiguration, UserUpdateConfiguration, Workspace,
+        WorkspaceDefaults,
+    },
+};
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, num::ParseIntError, sync::Arc, time::Duration};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+/// Where template images expect the project to work on
+pub const DEFAULT_WORKSPACE_PATH: &str = "/home/playground/workspace";
+
+/// Where template Dockerfiles are fetched from by image builds
+pub const DEFAULT_PLAYGROUND_REPOSITORY: &str = "paritytech/substrate-playground";
+
+/// Number of versions kept per template, unless configured otherwise
+pub const DEFAULT_TEMPLATE_HISTORY_SIZE: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Environment {
+    pub secured: bool,
+    pub host: String,
+    pub namespace: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Configuration {
+    pub github_client_id: String,
+    pub session: SessionDefaults,
+    /// Quotas applying to members of GitHub organizations, by organization
+    #[serde(skip_serializing)]
+    pub organizations: BTreeMap<String, Quota>,
+    pub workspace: Option<WorkspaceDefaults>,
+    #[serde(skip_serializing)]
+    pub build: Option<BuildDefaults>,
+    /// Number of versions kept per template, including the current one
+    #[serde(skip_serializing)]
+    pub template_history_size: usize,
+}
+
+impl Configuration {
+    /// Retrieves 'static' configuration from Env variables
+    pub fn from_env() -> Result<Self> {
+        let github_client_id =
+            env::var("GITHUB_CLIENT_ID").map_err(|_| Error::MissingData("GITHUB_CLIENT_ID"))?;
+        let session_default_duration = env::var("SESSION_DEFAULT_DURATION")
+            .map_err(|_| Error::MissingData("SESSION_DEFAULT_DURATION"))?;
+        let session_max_duration = env::var("SESSION_MAX_DURATION")
+            .map_err(|_| Error::MissingData("SESSION_MAX_DURATION"))?;
+        let session_default_pool_affinity = env::var("SESSION_DEFAULT_POOL_AFFINITY")
+            .map_err(|_| Error::MissingData("SESSION_DEFAULT_POOL_AFFINITY"))?;
+        let session_default_max_per_node = env::var("SESSION_DEFAULT_MAX_PER_NODE")
+            .map_err(|_| Error::MissingData("SESSION_DEFAULT_MAX_PER_NODE"))?;
+        let session_default_max_per_user = env::var("SESSION_DEFAULT_MAX_PER_USER")
+            .map_err(|_| Error::MissingData("SESSION_DEFAULT_MAX_PER_USER"))?;
+        // Optional, a YAML map of organization ids to `Quota`s
+        let organizations = match env::var("ORGANIZATION_QUOTAS") {
+            Ok(quotas) => {
+                serde_yaml::from_str(&quotas).map_err(|err| Error::Failure(err.into()))?
+            }
+            Err(_) => BTreeMap::new(),
+        };
+        // Optional, YAML `Resources` and `ResourceQuantities`
+        let session_default_resources = match env::var("SESSION_DEFAULT_RESOURCES") {
+            Ok(resources) => {
+                serde_yaml::from_str(&resources).map_err(|err| Error::Failure(err.into()))?
+            }
+            Err(_) => Resources::default(),
+        };
+        // Optional, in minutes
+        let session_idle_timeout = match env::var("SESSION_IDLE_TIMEOUT") {
+            Ok(timeout) => Some(str_to_session_duration_minutes(&timeout)?),
+            Err(_) => None,
+        };
+        // Optional, in minutes
+        let session_expiry_warning = match env::var("SESSION_EXPIRY_WARNING") {
+            Ok(warning) => str_to_session_duration_minutes(&warning)?,
+            Err(_) => Duration::from_secs(5 * 60),
+        };
+        let session_max_resources = match env::var("SESSION_MAX_RESOURCES") {
+            Ok(resources) => {
+                serde_yaml::from_str(&resources).map_err(|err| Error::Failure(err.into()))?
+            }
+            Err(_) => Default::default(),
+        };
+
+        // Optional, workspaces are enabled by setting their size
+        let workspace = match env::var("WORKSPACE_SIZE") {
+            Ok(size) => Some(WorkspaceDefaults {
+                size,
+                path: env::var("WORKSPACE_PATH")
+                    .unwrap_or_else(|_| DEFAULT_WORKSPACE_PATH.to_string()),
+                storage_class: env::var("WORKSPACE_STORAGE_CLASS").ok(),
+                retention_days: match env::var("WORKSPACE_RETENTION_DAYS") {
+                    Ok(days) => Some(
+                        days.parse()
+                            .map_err(|err: ParseIntError| Error::Failure(err.into()))?,
+                    ),
+                    Err(_) => None,
+                },
+            }),
+            Err(_) => None,
+        };
+
+        // Optional, builds are enabled by setting the registry images are pushed to
+        let build = match env::var("BUILD_REGISTRY") {
+            Ok(registry) => Some(BuildDefaults {
+                registry,
+                push_secret: env::var("BUILD_PUSH_SECRET").ok(),
+                playground: Repository {
+                    url: env::var("BUILD_PLAYGROUND_REPOSITORY")
+                        .unwrap_or_else(|_| DEFAULT_PLAYGROUND_REPOSITORY.to_string()),
+                    reference: env::var("BUILD_PLAYGROUND_REF").ok(),
+                },
+            }),
+            Err(_) => None,
+        };
+
+        // Optional
+        let template_history_size = match env::var("TEMPLATE_HISTORY_SIZE") {
+            Ok(size) => size
+                .parse()
+                .map_err(|err: ParseIntError| Error::Failure(err.into()))?,
+            Err(_) => DEFAULT_TEMPLATE_HISTORY_SIZE,
+        };
+
+        Ok(Configuration {
+            github_client_id,
+            session: SessionDefaults {
+                duration: str_to_session_duration_minutes(&session_default_duration)?,
+                max_duration: str_to_session_duration_minutes(&session_max_duration)?,
+                pool_affinity: session_default_pool_affinity,
+                max_sessions_per_pod: session_default_max_per_node
+                    .parse()
+                    .map_err(|err: ParseIntError| Error::Failure(err.into()))?,
+                max_sessions_per_user: session_default_max_per_user
+                    .parse()
+                    .map_err(|err: ParseIntError| Error::Failure(err.into()))?,
+                resources: session_default_resources,
+                max_resources: session_max_resources,
+                idle_timeout: session_idle_timeout,
+                expiry_warning: session_expiry_warning,
+            },
+            organizations,
+            workspace,
+            build,
+            template_history_size: template_history_size.max(1),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Secrets {
+    pub github_client_secret: String,
+}
+
+impl Secrets {
+    pub fn from_env() -> Result<Self> {
+        let github_client_secret = env::var("GITHUB_CLIENT_SECRET")
+            .map_err(|_| Error::MissingData("GITHUB_CLIENT_SECRET"))?;
+        Ok(Secrets {
+            github_client_secret,
+        })
+    }
+}
+
+pub fn str_to_session_duration_minutes(str: &str) -> Result<Duration> {
+    Ok(Duration::from_secs(
+        str.parse::<u64>()
+            .map_err(|err| Error::Failure(err.into()))?
+            * 60,
+    ))
+}
+
+///
+/// The id of the pool a new session is deployed on.
+///
+/// Either requested via `conf`, or the pool affinity of `user`, or the configured default.
+///
+pub fn pool_affinity(
+    configuration: &Configuration,
+    user: &LoggedUser,
+    conf: &SessionConfiguration,
+) -> String {
+    conf.pool_affinity
+        .clone()
+        .or_else(|| user.pool_affinity.clone())
+        .unwrap_or_else(|| configuration.session.pool_affinity.clone())
+}
+
+///
+/// The resources of session containers created from `template`.
+///
+/// Resources not set by `template` default to those configured. Fails if some exceed the configured maximums.
+///
+pub fn session_resources(configuration: &Configuration, template: &Template) -> Result<Resources> {
+    let resources = template
+        .runtime
+        .as_ref()
+        .and_then(|runtime| runtime.resources.clone())
+        .unwrap_or_default()
+        .or(configuration.session.resources.clone());
+    let problems = resources.validate(&configuration.session.max_resources);
+    if problems.is_empty() {
+        Ok(resources)
+    } else {
+        Err(Error::Validation(problems.join(", ")))
+    }
+}
+
+///
+/// The repository cloned in a session created with `conf`, if it overrides the one of `template`.
+///
+/// A ref alone applies to the template repository.
+///
+pub fn session_repository(
+    template: &Template,
+    conf: &SessionConfiguration,
+) -> Result<Option<Repository>> {
+    let url = match (&conf.repository, &conf.reference) {
+        (None, None) => return Ok(None),
+        (Some(url), _) => url.clone(),
+        (None, Some(_)) => template
+            .repository
+            .clone()
+            .ok_or_else(|| Error::Validation("template has no repository".to_string()))?,
+    };
+    let repository = Repository {
+        url,
+        reference: conf.reference.clone(),
+    };
+    let problems = repository.validate();
+    if problems.is_empty() {
+        Ok(Some(repository))
+    } else {
+        Err(Error::Validation(problems.join(", ")))
+    }
+}
+
+///
+/// The repository an image of `template` is built from with `conf`.
+///
+/// Defaults to the template repository, at its ref unless another repository is requested.
+///
+pub fn build_repository(template: &Template, conf: &BuildConfiguration) -> Result<Repository> {
+    let repository = match &conf.repository {
+        Some(url) => Repository {
+            url: url.clone(),
+            reference: conf.reference.clone(),
+        },
+        None => Repository {
+            url: template
+                .repository
+                .clone()
+                .ok_or_else(|| Error::Validation("template has no repository".to_string()))?,
+            reference: conf
+                .reference
+                .clone()
+                .or_else(|| template.reference.clone()),
+        },
+    };
+    let problems = repository.validate();
+    if problems.is_empty() {
+        Ok(repository)
+    } else {
+        Err(Error::Validation(problems.join(", ")))
+    }
+}
+
+///
+/// The version of a template a session created with `conf` runs, among `versions` of this template.
+///
+/// Either the version requested via `conf`, or the latest one.
+///
+pub fn session_template(
+    mut versions: Vec<TemplateVersion>,
+    conf: &SessionConfiguration,
+) -> Result<TemplateVersion> {
+    match conf.template_version {
+        Some(version) => versions
+            .into_iter()
+            .find(|candidate| candidate.version == version)
+            .ok_or(Error::NotFound("template version")),
+        None => versions
+            .pop()
+            .ok_or_else(|| Error::TemplateNotFound(conf.template.clone())),
+    }
+}
+
+///
+/// The prefix of ids generated for sessions of `user_id`.
+///
+/// Ids are used as part of DNS names (see `subdomain`), so they must be lowercase.
+///
+pub fn session_id_prefix(user_id: &str) -> String {
+    format!("{}-", user_id.to_lowercase())
+}
+
+pub fn subdomain(host: &str, session_id: &str) -> String {
+    format!("{}.{}", session_id, host)
+}
+
+/// Turns a broadcast `Receiver` into a `Stream`, skipping messages missed by slow consumers
+pub fn receiver_stream<T: Clone + Send + 'static>(receiver: Receiver<T>) -> BoxStream<'static, T> {
+    stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(value) => return Some((value, receiver)),
+                Err(RecvError::Lagged(count)) => log::warn!("Skipped {} messages", count),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Number of leftover resources deleted by `SessionBackend::collect_garbage`, per kind
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectedGarbage {
+    pub pods: usize,
+    pub services: usize,
+    pub ingresses: usize,
+}
+
+impl CollectedGarbage {
+    pub fn is_empty(&self) -> bool {
+        *self == CollectedGarbage::default()
+    }
+}
+
+///
+/// Operations required by `Manager` to handle templates, users, sessions and pools.
+///
+/// `kubernetes::Engine` is the production implementation, `memory::MemoryEngine` allows to run the API without a cluster.
+///
+#[async_trait]
+pub trait SessionBackend: Send + Sync {
+    fn env(&self) -> &Environment;
+
+    fn configuration(&self) -> &Configuration;
+
+    fn secrets(&self) -> &Secrets;
+
+    // Templates
+
+    async fn list_templates(&self) -> Result<BTreeMap<String, Template>>;
+
+    /// Creates template `id`, or replaces it
+    async fn update_template(&self, id: &str, template: Template) -> Result<()>;
+
+    async fn delete_template(&self, id: &str) -> Result<()>;
+
+    /// Versions kept of template `id`, oldest first. The last one is the latest valid version.
+    async fn list_template_versions(&self, id: &str) -> Result<Vec<TemplateVersion>>;
+
+    // Users
+
+    async fn get_user(&self, id: &str) -> Result<Option<User>>;
+
+    async fn list_users(&self) -> Result<BTreeMap<String, User>>;
+
+    async fn create_user(&self, id: String, conf: UserConfiguration) -> Result<()>;
+
+    async fn update_user(&self, id: String, conf: UserUpdateConfiguration) -> Result<()>;
+
+    async fn delete_user(&self, id: String) -> Result<()>;
+
+    // Sessions
+
+    async fn get_session(&self, id: &str) -> Result<Option<Session>>;
+
+    /// Lists all currently running sessions
+    async fn list_sessions(&self) -> Result<BTreeMap<String, Session>>;
+
+    /// Creates a new session owned by `user`. Returns its generated id.
+    async fn create_session(&self, user: &LoggedUser, conf: SessionConfiguration)
+        -> Result<String>;
+
+    async fn update_session(
+        &self,
+        session_id: &str,
+        conf: SessionUpdateConfiguration,
+    ) -> Result<()>;
+
+    async fn delete_session(&self, id: &str) -> Result<()>;
+
+    /// Streams all `Session` changes happening from now on
+    fn session_events(&self) -> BoxStream<'static, SessionEvent>;
+
+    // Pools
+
+    async fn get_pool(&self, id: &str) -> Result<Option<Pool>>;
+
+    async fn list_pools(&self) -> Result<BTreeMap<String, Pool>>;
+
+    // Workspaces
+
+    async fn get_workspace(&self, user_id: &str) -> Result<Option<Workspace>>;
+
+    async fn list_workspaces(&self) -> Result<BTreeMap<String, Workspace>>;
+
+    /// Archives the content of the workspace of `user_id`, as a gzipped tarball
+    async fn snapshot_workspace(&self, user_id: &str) -> Result<Vec<u8>>;
+
+    async fn delete_workspace(&self, user_id: &str) -> Result<()>;
+
+    // Builds
+
+    async fn get_build(&self, id: &str) -> Result<Option<Build>>;
+
+    async fn list_builds(&self) -> Result<BTreeMap<String, Build>>;
+
+    /// Starts building an image of template `template_id` from `repository`. Returns the build id.
+    async fn create_build(&self, template_id: &str, repository: Repository) -> Result<String>;
+
+    // Maintenance
+
+    /// Deletes resources left behind by partially failed session creations or deletions
+    async fn collect_garbage(&self) -> Result<CollectedGarbage>;
+}
+
+///
+/// Creates the `SessionBackend` selected via the `PLAYGROUND_BACKEND` env variable.
+///
+/// Accepted values are `kubernetes` (the default) and `memory`.
+///
+pub async fn new() -> Result<Arc<dyn SessionBackend>> {
+    match env::var("PLAYGROUND_BACKEND").as_deref() {
+        Ok("kubernetes") | Err(_) => {
+            let engine = Engine::new().await?;
+            engine.clone().spawn_watchers();
+            engine.clone().spawn_reconciler();
+            Ok(Arc::new(engine))
+        }
+        Ok("memory") => Ok(Arc::new(MemoryEngine::new()?)),
+        Ok(other) => Err(Error::Failure(
+            format!("Unknown PLAYGROUND_BACKEND: {}", other).into(),
+        )),
+    }
+}
@@ -0,0 +1,167 @@
+//! Watch-driven local copies of k8s resources, sparing API server round-trips on reads
+use crate::error::{Error, Result};
+use futures::{pin_mut, TryStreamExt};
+use kube::api::{Api, ListParams, Meta, WatchEvent};
+use log::{error, warn};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Duration,
+};
+use tokio::sync::broadcast;
+
+/// A change applied to a `Cache`
+#[derive(Clone, Debug)]
+pub enum Event<K> {
+    /// Resource was created or modified
+    Applied(K),
+    /// Resource was deleted
+    Deleted(K),
+}
+
+struct State<K> {
+    // Last seen `resourceVersion`, watches resume from there
+    version: String,
+    items: BTreeMap<String, K>,
+}
+
+///
+/// A local copy of all resources of type `K` accessible via an `Api`.
+///
+/// Populated by a full list on creation, then kept up to date by `watch`.
+/// Each change is broadcasted to `subscribe`rs.
+///
+#[derive(Clone)]
+pub struct Cache<K> {
+    api: Api<K>,
+    params: ListParams,
+    state: Arc<RwLock<State<K>>>,
+    events: broadcast::Sender<Event<K>>,
+}
+
+impl<K> Cache<K>
+where
+    K: k8s_openapi::Resource + Clone + DeserializeOwned + Meta,
+{
+    const RETRY_PERIOD: Duration = Duration::from_secs(5);
+    const EVENTS_CAPACITY: usize = 64;
+
+    pub async fn new(api: Api<K>, params: ListParams) -> Result<Self> {
+        let (events, _) = broadcast::channel(Self::EVENTS_CAPACITY);
+        let cache = Cache {
+            api,
+            params,
+            state: Arc::new(RwLock::new(State {
+                version: "0".to_string(),
+                items: BTreeMap::new(),
+            })),
+            events,
+        };
+        cache.reset().await?;
+        Ok(cache)
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, State<K>>> {
+        self.state
+            .read()
+            .map_err(|_| Error::Failure("Failed to acquire cache lock".into()))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, State<K>>> {
+        self.state
+            .write()
+            .map_err(|_| Error::Failure("Failed to acquire cache lock".into()))
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<K>> {
+        Ok(self.read()?.items.get(name).cloned())
+    }
+
+    pub fn list(&self) -> Result<Vec<K>> {
+        Ok(self.read()?.items.values().cloned().collect())
+    }
+
+    /// Receives all changes applied from now on. Slow receivers might miss some of them.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event<K>> {
+        self.events.subscribe()
+    }
+
+    fn notify(&self, event: Event<K>) {
+        // Fails only when there is no receiver
+        let _ = self.events.send(event);
+    }
+
+    // Replaces the whole state with a fresh list
+    async fn reset(&self) -> Result<()> {
+        let list = self.api.list(&self.params).await.map_err(Error::K8s)?;
+        let version = list.metadata.resource_version.unwrap_or_default();
+        let items: BTreeMap<String, K> = list
+            .items
+            .into_iter()
+            .map(|item| (item.name(), item))
+            .collect();
+        let previous = std::mem::replace(
+            &mut *self.write()?,
+            State {
+                version,
+                items: items.clone(),
+            },
+        );
+        // Changes missed while not watching are not known precisely, report the whole new state
+        for (name, item) in previous.items {
+            if !items.contains_key(&name) {
+                self.notify(Event::Deleted(item));
+            }
+        }
+        for (_, item) in items {
+            self.notify(Event::Applied(item));
+        }
+        Ok(())
+    }
+
+    // Applies all events following the last seen version, until the server closes the watch
+    async fn poll(&self) -> Result<()> {
+        let version = self.read()?.version.clone();
+        let stream = self
+            .api
+            .watch(&self.params, &version)
+            .await
+            .map_err(Error::K8s)?;
+        pin_mut!(stream);
+        while let Some(event) = stream.try_next().await.map_err(Error::K8s)? {
+            let mut state = self.write()?;
+            match event {
+                WatchEvent::Added(item) | WatchEvent::Modified(item) => {
+                    state.version = item.resource_ver().unwrap_or(state.version.clone());
+                    state.items.insert(item.name(), item.clone());
+                    self.notify(Event::Applied(item));
+                }
+                WatchEvent::Deleted(item) => {
+                    state.version = item.resource_ver().unwrap_or(state.version.clone());
+                    state.items.remove(&item.name());
+                    self.notify(Event::Deleted(item));
+                }
+                WatchEvent::Bookmark(bookmark) => {
+                    state.version = bookmark.metadata.resource_version;
+                }
+                WatchEvent::Error(err) => return Err(Error::K8s(kube::Error::Api(err))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps this cache up to date, forever
+    pub async fn watch(self) {
+        loop {
+            if let Err(err) = self.poll().await {
+                // Most likely the last seen version expired, start over from a fresh list
+                warn!("Failed to watch {}: {}", K::KIND, err);
+                tokio::time::sleep(Self::RETRY_PERIOD).await;
+                if let Err(err) = self.reset().await {
+                    error!("Failed to list {}: {}", K::KIND, err);
+                }
+            }
+        }
+    }
+}
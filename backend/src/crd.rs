@@ -0,0 +1,227 @@
+//! Custom Resources managed by the kubernetes backend
+use crate::types::{ContainerStatus, Phase, Repository, Resources, Template};
+use k8s_openapi::{apimachinery::pkg::apis::meta::v1::ObjectMeta, Metadata, Resource};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub const GROUP: &str = "playground.substrate.io";
+pub const VERSION: &str = "v1";
+
+// Implements the traits required by `kube::Api` for a Custom Resource type
+macro_rules! custom_resource {
+    ($type:ty, $kind:literal) => {
+        impl Resource for $type {
+            const API_VERSION: &'static str = "playground.substrate.io/v1";
+            const GROUP: &'static str = GROUP;
+            const KIND: &'static str = $kind;
+            const VERSION: &'static str = VERSION;
+        }
+
+        impl Metadata for $type {
+            type Ty = ObjectMeta;
+
+            fn metadata(&self) -> &ObjectMeta {
+                &self.metadata
+            }
+
+            fn metadata_mut(&mut self) -> &mut ObjectMeta {
+                &mut self.metadata
+            }
+        }
+    };
+}
+
+/// Status of a `Template`, as computed by the backend
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateStatus {
+    /// `true` if the template can be used to create sessions
+    pub valid: bool,
+    /// Human readable list of problems preventing this template to be used
+    #[serde(default)]
+    pub problems: Vec<String>,
+}
+
+///
+/// A `Template` stored as a Custom Resource. The resource name is used as template id.
+///
+/// `spec` is kept as raw JSON so that templates failing to parse can still be listed and have their status updated.
+///
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateResource {
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub spec: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<TemplateStatus>,
+}
+
+impl TemplateResource {
+    pub fn new(id: &str, template: &Template) -> serde_json::Result<Self> {
+        Ok(TemplateResource {
+            api_version: <Self as Resource>::API_VERSION.to_string(),
+            kind: <Self as Resource>::KIND.to_string(),
+            metadata: ObjectMeta {
+                name: Some(id.to_string()),
+                ..Default::default()
+            },
+            spec: serde_json::to_value(template)?,
+            status: None,
+        })
+    }
+}
+
+custom_resource!(TemplateResource, "Template");
+
+/// Desired state of a `Session`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSpec {
+    /// Id of the user owning this session
+    pub user_id: String,
+    /// Id of the template this session has been created from
+    #[serde(default)]
+    pub template_id: String,
+    /// Version of the template this session has been created from
+    #[serde(default)]
+    pub template_version: u64,
+    /// Snapshot of the template this session has been created from
+    pub template: Template,
+    /// Duration of this session, in minutes
+    pub duration: u64,
+    /// Node pool this session is preferably deployed on
+    pub pool_affinity: String,
+    /// Resources of the session container, resolved at creation
+    #[serde(default)]
+    pub resources: Resources,
+    /// Repository cloned in the session workspace, overriding the template one
+    pub repository: Option<Repository>,
+}
+
+/// Observed state of a `Session`, as reported by the backend reconcile loop
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionStatus {
+    pub phase: Phase,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub message: String,
+    /// Start time of the session pod, in seconds since UNIX epoch
+    pub start_time: Option<i64>,
+    /// Node the session pod is running on
+    pub node: Option<String>,
+    pub container: Option<ContainerStatus>,
+}
+
+///
+/// A `Session` stored as a Custom Resource. The resource name is used as session id.
+///
+/// It owns the session Pod and Service, which are converged to `spec` by the backend.
+///
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResource {
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub spec: SessionSpec,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<SessionStatus>,
+}
+
+impl SessionResource {
+    /// A new `SessionResource` whose name will be generated by the API server from `prefix`
+    pub fn generate(prefix: &str, spec: SessionSpec) -> Self {
+        SessionResource {
+            api_version: <Self as Resource>::API_VERSION.to_string(),
+            kind: <Self as Resource>::KIND.to_string(),
+            metadata: ObjectMeta {
+                generate_name: Some(prefix.to_string()),
+                ..Default::default()
+            },
+            spec,
+            status: None,
+        }
+    }
+}
+
+custom_resource!(SessionResource, "Session");
+
+/// Generates the OpenAPI v3 schema of `T`, inlined as required by Custom Resource Definitions
+fn schema<T: JsonSchema>() -> Value {
+    let generator = SchemaSettings::openapi3()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator();
+    let mut schema = json!(generator.into_root_schema_for::<T>().schema);
+    if let Some(object) = schema.as_object_mut() {
+        object.remove("title");
+    }
+    schema
+}
+
+fn crd<Spec: JsonSchema, Status: JsonSchema>(kind: &str, plural: &str, columns: Value) -> Value {
+    json!({
+        "apiVersion": "apiextensions.k8s.io/v1",
+        "kind": "CustomResourceDefinition",
+        "metadata": {
+            "name": format!("{}.{}", plural, GROUP),
+        },
+        "spec": {
+            "group": GROUP,
+            "scope": "Namespaced",
+            "names": {
+                "plural": plural,
+                "singular": kind.to_lowercase(),
+                "kind": kind,
+            },
+            "versions": [{
+                "name": VERSION,
+                "served": true,
+                "storage": true,
+                "subresources": {
+                    "status": {},
+                },
+                "additionalPrinterColumns": columns,
+                "schema": {
+                    "openAPIV3Schema": {
+                        "type": "object",
+                        "required": ["spec"],
+                        "properties": {
+                            "spec": schema::<Spec>(),
+                            "status": schema::<Status>(),
+                        },
+                    },
+                },
+            }],
+        },
+    })
+}
+
+/// All `CustomResourceDefinition`s required by the backend, generated from their Rust types
+pub fn crds() -> Vec<Value> {
+    vec![
+        crd::<Template, TemplateStatus>(
+            "Template",
+            "templates",
+            json!([
+                {"name": "Image", "type": "string", "jsonPath": ".spec.image"},
+                {"name": "Valid", "type": "boolean", "jsonPath": ".status.valid"},
+                {"name": "Problems", "type": "string", "jsonPath": ".status.problems"},
+            ]),
+        ),
+        crd::<SessionSpec, SessionStatus>(
+            "Session",
+            "sessions",
+            json!([
+                {"name": "User", "type": "string", "jsonPath": ".spec.userId"},
+                {"name": "Template", "type": "string", "jsonPath": ".spec.template.name"},
+                {"name": "Phase", "type": "string", "jsonPath": ".status.phase"},
+                {"name": "Node", "type": "string", "jsonPath": ".status.node"},
+            ]),
+        ),
+    ]
+}
@@ -1,17 +1,78 @@
-///! Error type for the whole project
-///
+//! Error type for the whole project
 use std::result;
 use thiserror::Error;
 
 /// A specialized [`Result`] type.
 pub type Result<T> = result::Result<T, Error>;
 
+///
+/// All errors that can be surfaced to clients.
+///
+/// Each variant maps to a stable `code` and an HTTP `status`, so that clients can react without parsing messages.
+///
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("data store disconnected")]
-    Unauthorized(/*Permission*/),
-    #[error("data store disconnected")]
+    /// No user is logged in
+    #[error("Authentication required")]
+    Unauthorized(),
+    /// The logged user lacks the rights for this operation
+    #[error("Forbidden: {0}")]
+    Forbidden(&'static str),
+    #[error("No matching {0}")]
+    NotFound(&'static str),
+    /// A `Quota` of the logged user would be exceeded
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("No capacity left in pool {0}")]
+    CapacityExhausted(String),
+    #[error("No matching template {0}")]
+    TemplateNotFound(String),
+    #[error("Invalid request: {0}")]
+    Validation(String),
+    /// The request is valid, but conflicts with the current state of a resource
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Kubernetes API failure: {0}")]
+    K8s(#[from] kube::Error),
+    #[error("GitHub API failure: {0}")]
+    GitHub(String),
+    /// Some expected data is missing, most likely a misconfiguration
+    #[error("Missing data: {0}")]
     MissingData(&'static str),
-    #[error("data store disconnected")]
-    Failure(#[from] Box<dyn std::error::Error>),
+    #[error("Internal failure: {0}")]
+    Failure(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// A stable, machine-readable identifier of this error
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Unauthorized() => "unauthorized",
+            Error::Forbidden(_) => "forbidden",
+            Error::NotFound(_) => "not_found",
+            Error::QuotaExceeded(_) => "quota_exceeded",
+            Error::CapacityExhausted(_) => "capacity_exhausted",
+            Error::TemplateNotFound(_) => "template_not_found",
+            Error::Validation(_) => "validation",
+            Error::Conflict(_) => "conflict",
+            Error::K8s(_) => "k8s_failure",
+            Error::GitHub(_) => "github_failure",
+            Error::MissingData(_) | Error::Failure(_) => "internal",
+        }
+    }
+
+    /// The HTTP status code this error is reported with
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::Unauthorized() => 401,
+            Error::Forbidden(_) => 403,
+            Error::NotFound(_) => 404,
+            Error::QuotaExceeded(_) => 429,
+            Error::CapacityExhausted(_) => 503,
+            Error::Conflict(_) => 409,
+            Error::TemplateNotFound(_) | Error::Validation(_) => 422,
+            Error::K8s(_) | Error::GitHub(_) => 502,
+            Error::MissingData(_) | Error::Failure(_) => 500,
+        }
+    }
 }
@@ -0,0 +1,501 @@
+//! JSON-RPC 2.0 access to `Manager` operations
+//!
+//! See https://www.jsonrpc.org/specification
+use crate::{
+    error::Error,
+    manager::Manager,
+    types::{
+        BuildConfiguration, LoggedUser, RollbackConfiguration, SessionConfiguration,
+        SessionUpdateConfiguration, UserConfiguration, UserUpdateConfiguration,
+    },
+};
+use futures::future;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+
+const VERSION: &str = "2.0";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Reported for all `Error`s, whose own code is available as `data.code`
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+    // Absent for notifications. `null` is a valid id.
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+}
+
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorObject {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl ErrorObject {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        ErrorObject {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<Error> for ErrorObject {
+    fn from(error: Error) -> Self {
+        ErrorObject {
+            code: SERVER_ERROR,
+            message: error.to_string(),
+            data: Some(json!({ "code": error.code() })),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IdParams {
+    id: String,
+}
+
+// A `Template`, or a merge patch of it, as an object or a YAML or JSON string
+#[derive(Deserialize)]
+struct TemplateParams {
+    id: String,
+    template: Value,
+}
+
+#[derive(Deserialize)]
+struct DeleteTemplateParams {
+    id: String,
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Deserialize)]
+struct WithId<T> {
+    id: String,
+    #[serde(flatten)]
+    conf: T,
+}
+
+// The YAML or JSON document of `template`, passed either as an object or as a string
+fn template_content(template: Value) -> String {
+    match template {
+        Value::String(content) => content,
+        other => other.to_string(),
+    }
+}
+
+fn response(id: Value, result: Result<Value, ErrorObject>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": VERSION, "result": result, "id": id }),
+        Err(error) => json!({ "jsonrpc": VERSION, "error": error, "id": id }),
+    }
+}
+
+fn params<T: DeserializeOwned>(params: Option<Value>) -> Result<T, ErrorObject> {
+    // Omitted params are treated as an empty object; only named params are supported
+    serde_json::from_value(params.unwrap_or_else(|| json!({})))
+        .map_err(|err| ErrorObject::new(INVALID_PARAMS, err.to_string()))
+}
+
+fn to_value<T: Serialize>(result: crate::error::Result<T>) -> Result<Value, ErrorObject> {
+    let value = result?;
+    serde_json::to_value(value).map_err(|err| Error::Failure(err.into()).into())
+}
+
+fn logged(user: &Option<LoggedUser>) -> Result<&LoggedUser, ErrorObject> {
+    user.as_ref().ok_or_else(|| Error::Unauthorized().into())
+}
+
+// Executes `method` on behalf of `user`, if any
+async fn call(
+    manager: &Manager,
+    user: &Option<LoggedUser>,
+    method: &str,
+    p: Option<Value>,
+) -> Result<Value, ErrorObject> {
+    match method {
+        "get" => match user {
+            Some(user) => to_value(manager.get(user.clone()).await),
+            None => to_value(manager.get_unlogged().await),
+        },
+        // Templates
+        "templates.list" => to_value(manager.list_templates(user.as_ref()).await),
+        "templates.update" => {
+            let TemplateParams { id, template } = params(p)?;
+            to_value(
+                manager
+                    .update_template(logged(user)?, &id, &template_content(template))
+                    .await,
+            )
+        }
+        "templates.patch" => {
+            let TemplateParams { id, template } = params(p)?;
+            to_value(
+                manager
+                    .patch_template(logged(user)?, &id, &template_content(template))
+                    .await,
+            )
+        }
+        "templates.delete" => {
+            let DeleteTemplateParams { id, force } = params(p)?;
+            to_value(manager.delete_template(logged(user)?, &id, force).await)
+        }
+        "templates.versions" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.list_template_versions(logged(user)?, &id).await)
+        }
+        "templates.rollback" => {
+            let WithId { id, conf } = params::<WithId<RollbackConfiguration>>(p)?;
+            to_value(
+                manager
+                    .rollback_template(logged(user)?, &id, conf.version)
+                    .await,
+            )
+        }
+        // Users
+        "users.get" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.get_user(logged(user)?, &id).await)
+        }
+        "users.list" => to_value(manager.list_users(logged(user)?).await),
+        "users.create" => {
+            let WithId { id, conf } = params::<WithId<UserConfiguration>>(p)?;
+            to_value(manager.create_user(logged(user)?, id, conf).await)
+        }
+        "users.update" => {
+            let WithId { id, conf } = params::<WithId<UserUpdateConfiguration>>(p)?;
+            to_value(manager.update_user(logged(user)?.clone(), id, conf).await)
+        }
+        "users.delete" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.delete_user(logged(user)?, id).await)
+        }
+        // Sessions of the current user
+        "session.list" => to_value(manager.list_user_sessions(logged(user)?).await),
+        "session.create" => {
+            let conf: SessionConfiguration = params(p)?;
+            to_value(manager.create_session(logged(user)?, conf).await)
+        }
+        "queue.list" => to_value(manager.list_queued_sessions(logged(user)?).await),
+        "queue.cancel" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.cancel_queued_session(logged(user)?, &id).await)
+        }
+        // Sessions, accessible to their owner and admins
+        "sessions.get" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.get_session(logged(user)?, &id).await)
+        }
+        "sessions.list" => to_value(manager.list_sessions(logged(user)?).await),
+        "sessions.update" => {
+            let WithId { id, conf } = params::<WithId<SessionUpdateConfiguration>>(p)?;
+            to_value(manager.update_session(&id, logged(user)?, conf).await)
+        }
+        "sessions.heartbeat" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.record_activity(logged(user)?, &id).await)
+        }
+        "sessions.delete" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.delete_session(logged(user)?, &id).await)
+        }
+        // Pools
+        "pools.get" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.get_pool(logged(user)?, &id).await)
+        }
+        "pools.list" => to_value(manager.list_pools(logged(user)?).await),
+        // Workspaces, accessible to their owner and admins. Snapshots are only available via HTTP.
+        "workspaces.get" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.get_workspace(logged(user)?, &id).await)
+        }
+        "workspaces.list" => to_value(manager.list_workspaces(logged(user)?).await),
+        "workspaces.delete" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.delete_workspace(logged(user)?, &id).await)
+        }
+        // Builds, accessible to admins
+        "builds.get" => {
+            let IdParams { id } = params(p)?;
+            to_value(manager.get_build(logged(user)?, &id).await)
+        }
+        "builds.list" => to_value(manager.list_builds(logged(user)?).await),
+        "builds.create" => {
+            let WithId { id, conf } = params::<WithId<BuildConfiguration>>(p)?;
+            to_value(manager.create_build(logged(user)?, &id, conf).await)
+        }
+        _ => Err(ErrorObject::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {}", method),
+        )),
+    }
+}
+
+// Handles a single request. Returns `None` for notifications.
+async fn handle_one(manager: &Manager, user: &Option<LoggedUser>, request: Value) -> Option<Value> {
+    let request: Request = match serde_json::from_value(request) {
+        Ok(request) => request,
+        Err(err) => {
+            return Some(response(
+                Value::Null,
+                Err(ErrorObject::new(INVALID_REQUEST, err.to_string())),
+            ))
+        }
+    };
+    let id = request.id;
+    let result = if request.jsonrpc != VERSION {
+        Err(ErrorObject::new(
+            INVALID_REQUEST,
+            format!("Unsupported version {}", request.jsonrpc),
+        ))
+    } else {
+        call(manager, user, &request.method, request.params).await
+    };
+    id.map(|id| response(id, result))
+}
+
+///
+/// Handles a JSON-RPC `body` on behalf of `user`, if any.
+///
+/// Batched requests are executed concurrently. Returns `None` when there is nothing to respond,
+/// i.e. when only notifications were sent.
+///
+pub async fn handle(manager: &Manager, user: Option<LoggedUser>, body: &str) -> Option<Value> {
+    match serde_json::from_str(body) {
+        Ok(Value::Array(requests)) if requests.is_empty() => Some(response(
+            Value::Null,
+            Err(ErrorObject::new(INVALID_REQUEST, "Empty batch")),
+        )),
+        Ok(Value::Array(requests)) => {
+            let responses: Vec<Value> = future::join_all(
+                requests
+                    .into_iter()
+                    .map(|request| handle_one(manager, &user, request)),
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+            if responses.is_empty() {
+                None
+            } else {
+                Some(Value::Array(responses))
+            }
+        }
+        Ok(request) => handle_one(manager, &user, request).await,
+        Err(err) => Some(response(
+            Value::Null,
+            Err(ErrorObject::new(PARSE_ERROR, err.to_string())),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{Configuration, Secrets, DEFAULT_TEMPLATE_HISTORY_SIZE},
+        memory::MemoryEngine,
+        types::{Quota, SessionDefaults, Template},
+    };
+    use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+    // Serves a single public `template`
+    fn manager() -> Manager {
+        let mut tags = BTreeMap::new();
+        tags.insert(Template::PUBLIC_TAG.to_string(), "true".to_string());
+        let template = Template {
+            name: "template".to_string(),
+            image: "image".to_string(),
+            description: "".to_string(),
+            repository: None,
+            reference: None,
+            tags: Some(tags),
+            runtime: None,
+        };
+        let mut templates = BTreeMap::new();
+        templates.insert("template".to_string(), template);
+        let engine = MemoryEngine::with_configuration(
+            Configuration {
+                github_client_id: "client-id".to_string(),
+                session: SessionDefaults {
+                    duration: Duration::from_secs(60 * 60),
+                    max_duration: Duration::from_secs(120 * 60),
+                    pool_affinity: "default".to_string(),
+                    max_sessions_per_pod: 1,
+                    max_sessions_per_user: 5,
+                    resources: Default::default(),
+                    max_resources: Default::default(),
+                    idle_timeout: None,
+                    expiry_warning: Duration::from_secs(5 * 60),
+                },
+                organizations: BTreeMap::new(),
+                workspace: None,
+                build: None,
+                template_history_size: DEFAULT_TEMPLATE_HISTORY_SIZE,
+            },
+            Secrets {
+                github_client_secret: "client-secret".to_string(),
+            },
+            templates,
+        );
+        Manager::new(Arc::new(engine)).unwrap()
+    }
+
+    fn admin() -> LoggedUser {
+        LoggedUser {
+            id: "admin".to_string(),
+            admin: true,
+            organizations: vec![],
+            pool_affinity: None,
+            can_customize_duration: false,
+            can_customize_pool_affinity: false,
+            quota: Quota::default(),
+        }
+    }
+
+    async fn call(user: Option<LoggedUser>, body: Value) -> Option<Value> {
+        handle(&manager(), user, &body.to_string()).await
+    }
+
+    fn error_code(response: &Value) -> &Value {
+        &response["error"]["code"]
+    }
+
+    #[tokio::test]
+    async fn requests_are_answered() {
+        let response = call(
+            None,
+            json!({ "jsonrpc": "2.0", "method": "templates.list", "id": 1 }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["template"]["image"], "image");
+
+        // `null` ids are answered
+        let response = call(
+            None,
+            json!({ "jsonrpc": "2.0", "method": "templates.list", "id": null }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["id"], Value::Null);
+        assert!(response.get("result").is_some());
+    }
+
+    #[tokio::test]
+    async fn notifications_are_not_answered() {
+        assert_eq!(
+            call(
+                None,
+                json!({ "jsonrpc": "2.0", "method": "templates.list" })
+            )
+            .await,
+            None
+        );
+        assert_eq!(
+            call(None, json!([{ "jsonrpc": "2.0", "method": "unknown" }])).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn batches_are_answered_per_request() {
+        let response = call(
+            None,
+            json!([
+                { "jsonrpc": "2.0", "method": "templates.list", "id": 1 },
+                { "jsonrpc": "2.0", "method": "templates.list" },
+                { "jsonrpc": "1.0", "method": "templates.list", "id": 2 },
+                { "jsonrpc": "2.0", "method": "unknown", "id": 3 },
+                42,
+            ]),
+        )
+        .await
+        .unwrap();
+        let responses = response.as_array().unwrap();
+        assert_eq!(responses.len(), 4);
+        assert!(responses[0].get("result").is_some());
+        assert_eq!(error_code(&responses[1]), INVALID_REQUEST);
+        assert_eq!(responses[1]["id"], 2);
+        assert_eq!(error_code(&responses[2]), METHOD_NOT_FOUND);
+        assert_eq!(responses[2]["id"], 3);
+        assert_eq!(error_code(&responses[3]), INVALID_REQUEST);
+        assert_eq!(responses[3]["id"], Value::Null);
+
+        let response = call(None, json!([])).await.unwrap();
+        assert_eq!(error_code(&response), INVALID_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn errors_are_reported() {
+        let response = handle(&manager(), None, "{").await.unwrap();
+        assert_eq!(error_code(&response), PARSE_ERROR);
+        assert_eq!(response["id"], Value::Null);
+
+        let response = call(
+            Some(admin()),
+            json!({ "jsonrpc": "2.0", "method": "session.create", "params": { "template": 5 }, "id": 1 }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(error_code(&response), INVALID_PARAMS);
+
+        let response = call(
+            None,
+            json!({ "jsonrpc": "2.0", "method": "session.create", "params": { "template": "template" }, "id": 1 }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(error_code(&response), SERVER_ERROR);
+        assert_eq!(response["error"]["data"]["code"], "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn templates_can_be_passed_as_strings() {
+        let manager = manager();
+        for (id, template) in &[
+            ("yaml", json!("name: name\nimage: yaml\ndescription: ''\n")),
+            (
+                "json",
+                json!(r#"{"name": "name", "image": "json", "description": ""}"#),
+            ),
+            (
+                "object",
+                json!({ "name": "name", "image": "object", "description": "" }),
+            ),
+        ] {
+            let body = json!({
+                "jsonrpc": "2.0",
+                "method": "templates.update",
+                "params": { "id": id, "template": template },
+                "id": 1,
+            });
+            let response = handle(&manager, Some(admin()), &body.to_string())
+                .await
+                .unwrap();
+            assert_eq!(response["result"], Value::Null, "{}", response);
+        }
+        let templates = manager.list_templates(Some(&admin())).await.unwrap();
+        assert_eq!(templates["yaml"].image, "yaml");
+        assert_eq!(templates["json"].image, "json");
+        assert_eq!(templates["object"].image, "object");
+    }
+}
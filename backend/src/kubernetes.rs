@@ -1,34 +1,66 @@
 //! Helper methods ton interact with k8s
 use crate::{
+    backend::{
+        pool_affinity, receiver_stream, session_id_prefix, session_repository, session_resources,
+        session_template, subdomain, CollectedGarbage, Configuration, Environment, Secrets,
+        SessionBackend, DEFAULT_WORKSPACE_PATH,
+    },
+    cache::{Cache, Event},
+    crd::{SessionResource, SessionSpec, SessionStatus, TemplateResource, TemplateStatus},
     error::{Error, Result},
     types::{
-        self, ContainerPhase, LoggedUser, Phase, Pool, Session, SessionConfiguration,
-        SessionDefaults, SessionUpdateConfiguration, Template, User, UserConfiguration,
-        UserUpdateConfiguration,
+        self, Build, BuildDefaults, Command, ContainerPhase, LoggedUser, Phase, Pool, Repository,
+        ResourceQuantities, Resources, Session, SessionConfiguration, SessionEvent,
+        SessionUpdateConfiguration, Template, TemplateVersion, User, UserConfiguration,
+        UserUpdateConfiguration, Workspace, WorkspaceDefaults,
     },
+    users::{FileUserStore, UserStore},
 };
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
 use json_patch::{AddOperation, PatchOperation, RemoveOperation};
 use k8s_openapi::api::{
+    batch::v1::{Job, JobSpec},
     core::v1::{
-        Affinity, ConfigMap, Container, ContainerStatus, EnvVar, Node, NodeAffinity,
-        NodeSelectorRequirement, NodeSelectorTerm, Pod, PodSpec, PreferredSchedulingTerm, Service,
-        ServicePort, ServiceSpec,
+        Affinity, ConfigMap, Container, ContainerStatus, DownwardAPIVolumeFile,
+        DownwardAPIVolumeSource, EnvVar, ExecAction, Handler, KeyToPath, Lifecycle, Node,
+        NodeAffinity, NodeSelectorRequirement, NodeSelectorTerm, ObjectFieldSelector,
+        PersistentVolumeClaim, PersistentVolumeClaimSpec, PersistentVolumeClaimVolumeSource, Pod,
+        PodAffinity, PodAffinityTerm, PodSecurityContext, PodSpec, PodTemplateSpec,
+        PreferredSchedulingTerm, ResourceRequirements, SecretVolumeSource, SecurityContext,
+        Service, ServicePort, ServiceSpec, Volume, VolumeMount,
     },
     extensions::v1beta1::{
-        HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
+        HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule, IngressSpec,
+        IngressTLS,
     },
 };
-use k8s_openapi::apimachinery::pkg::{apis::meta::v1::ObjectMeta, util::intstr::IntOrString};
+use k8s_openapi::apimachinery::pkg::{
+    api::resource::Quantity,
+    apis::meta::v1::{LabelSelector, ObjectMeta, OwnerReference, Time},
+    util::intstr::IntOrString,
+};
 use kube::{
-    api::{Api, DeleteParams, ListParams, Meta, Patch, PatchParams, PostParams},
+    api::{Api, DeleteParams, ListParams, LogParams, Meta, Patch, PatchParams, PostParams},
     config::KubeConfigOptions,
     Client, Config,
 };
-use log::error;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
 use serde_json::json;
 use std::{
-    collections::BTreeMap, convert::TryFrom, env, num::ParseIntError, str::FromStr, time::Duration,
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
+    convert::TryFrom,
+    env,
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::Mutex as AsyncMutex,
+    task::JoinHandle,
+    time::{sleep, timeout},
 };
 
 const NODE_POOL_LABEL: &str = "cloud.google.com/gke-nodepool";
@@ -41,11 +73,72 @@ const COMPONENT_VALUE: &str = "session";
 const OWNER_LABEL: &str = "app.kubernetes.io/owner";
 const INGRESS_NAME: &str = "ingress";
 const TEMPLATE_ANNOTATION: &str = "playground.substrate.io/template";
-const SESSION_DURATION_ANNOTATION: &str = "playground.substrate.io/session_duration";
+const WARM_LABEL: &str = "playground.substrate.io/warm";
+const CLAIMED_ANNOTATION: &str = "playground.substrate.io/claimed";
+const RESOURCES_ANNOTATION: &str = "playground.substrate.io/resources";
+const TEMPLATE_VERSIONS_COMPONENT_VALUE: &str = "template-versions";
+// Identifies the template and resources a pod was created with, see `spec_hash`
+const SPEC_HASH_ANNOTATION: &str = "playground.substrate.io/spec-hash";
+const WORKSPACE_COMPONENT_VALUE: &str = "workspace";
+const USER_LABEL: &str = "playground.substrate.io/user";
+const LAST_USED_ANNOTATION: &str = "playground.substrate.io/last-used";
+const WORKSPACE_VOLUME: &str = "workspace";
+// User and group of the `playground` user of template images, see templates/Dockerfile.base
+const WORKSPACE_USER: i64 = 1000;
+const WORKSPACE_FS_GROUP: i64 = 1000;
+const EXPIRES_AT_ANNOTATION: &str = "playground.substrate.io/expires-at";
+// Exposes pod metadata to session containers
+const METADATA_VOLUME: &str = "playground";
+const METADATA_PATH: &str = "/etc/playground";
+const EXPIRES_AT_FILE: &str = "expires-at";
+// Leaves time for `pre_stop` commands to complete
+const PRE_STOP_GRACE_PERIOD_SECONDS: i64 = 30;
+const BUILD_COMPONENT_VALUE: &str = "build";
+const BUILD_TEMPLATE_LABEL: &str = "playground.substrate.io/build-template";
+const REPOSITORY_ANNOTATION: &str = "playground.substrate.io/repository";
+const IMAGE_ANNOTATION: &str = "playground.substrate.io/image";
+// Set on builds once their image is set on the template
+const APPLIED_ANNOTATION: &str = "playground.substrate.io/applied";
+const BUILD_VOLUME: &str = "build";
+const BUILD_SECRET_VOLUME: &str = "docker-config";
+const BUILD_PATH: &str = "/build";
+const BUILD_PLAYGROUND_PATH: &str = "/build/playground";
+const BUILD_TIMEOUT: Duration = Duration::from_secs(4 * 60 * 60);
+const GIT_IMAGE: &str = "alpine/git:v2.30.2";
+// The debug variant comes with a shell
+const KANIKO_IMAGE: &str = "gcr.io/kaniko-project/executor:v1.6.0-debug";
+const SNAPSHOT_IMAGE: &str = "busybox:1.33";
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(120);
+// How long new sessions are waited for in the sessions cache
+const CACHE_SYNC_TIMEOUT: Duration = Duration::from_secs(5);
 const USERS_CONFIG_MAP: &str = "playground-users";
 const TEMPLATES_CONFIG_MAP: &str = "playground-templates";
 const THEIA_WEB_PORT: i32 = 3000;
 
+// Gets a resource by name, `None` if it doesn't exist
+async fn get_optional<K: Clone + DeserializeOwned + Meta>(
+    api: &Api<K>,
+    name: &str,
+) -> Result<Option<K>> {
+    match api.get(name).await {
+        Ok(resource) => Ok(Some(resource)),
+        Err(kube::Error::Api(err)) if err.code == 404 => Ok(None),
+        Err(err) => Err(Error::Failure(err.into())),
+    }
+}
+
+// Creates `resource`, unless one with the same name already exists
+async fn create_if_missing<K: Clone + DeserializeOwned + Meta + serde::Serialize>(
+    api: &Api<K>,
+    resource: &K,
+) -> Result<()> {
+    match api.create(&PostParams::default(), resource).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(err)) if err.code == 409 => Ok(()),
+        Err(err) => Err(Error::K8s(err)),
+    }
+}
+
 async fn list_by_selector<K: Clone + DeserializeOwned + Meta>(
     api: &Api<K>,
     selector: String,
@@ -54,20 +147,83 @@ async fn list_by_selector<K: Clone + DeserializeOwned + Meta>(
         label_selector: Some(selector),
         ..ListParams::default()
     };
-    api.list(&params)
-        .await
-        .map(|l| l.items)
-        .map_err(|err| Error::Failure(err.into()))
+    api.list(&params).await.map(|l| l.items).map_err(Error::K8s)
+}
+
+// Deletes all `resources`, ignoring those already gone. Returns the number of deleted resources.
+async fn delete_all<K: Clone + DeserializeOwned + Meta>(
+    api: &Api<K>,
+    resources: &[K],
+) -> Result<usize> {
+    let mut count = 0;
+    for resource in resources {
+        match api.delete(&resource.name(), &DeleteParams::default()).await {
+            Ok(_) => count += 1,
+            Err(kube::Error::Api(err)) if err.code == 404 => (),
+            Err(err) => return Err(Error::Failure(err.into())),
+        }
+    }
+    Ok(count)
+}
+
+// The id of the session owning a resource, as defined by `OWNER_LABEL`
+fn owner_label<K: Meta>(resource: &K) -> Option<String> {
+    resource
+        .meta()
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(OWNER_LABEL).cloned())
+}
+
+// The id of the template a warm pod was started from, as defined by `WARM_LABEL`
+fn warm_label(pod: &Pod) -> Option<String> {
+    pod.metadata
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(WARM_LABEL).cloned())
+}
+
+// The hash of the template and resources a pod was created with, as defined by `SPEC_HASH_ANNOTATION`
+fn pod_spec_hash(pod: &Pod) -> Option<&String> {
+    pod.metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(SPEC_HASH_ANNOTATION))
+}
+
+// Warm pods are matched by this hash only, as other annotations can be added by webhooks or cloud providers.
+// `DefaultHasher` is stable for a given Rust release: warm pods are replaced once after upgrades.
+fn spec_hash(template: &str, resources: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    (template, resources).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
 }
 
 pub fn pod_name(user: &str) -> String {
     format!("{}-{}", COMPONENT_VALUE, user)
 }
 
+// Workspaces are per user. User ids are used as part of their name, so they must be lowercase.
+pub fn workspace_name(user_id: &str) -> String {
+    format!("{}-{}", WORKSPACE_COMPONENT_VALUE, user_id.to_lowercase())
+}
+
+fn now_annotation() -> String {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        .to_string()
+}
+
 pub fn service_name(session_id: &str) -> String {
     format!("{}-service-{}", COMPONENT_VALUE, session_id)
 }
 
+pub fn ingress_name(session_id: &str) -> String {
+    format!("{}-ingress-{}", COMPONENT_VALUE, session_id)
+}
+
 fn create_env_var(name: &str, value: &str) -> EnvVar {
     EnvVar {
         name: name.to_string(),
@@ -76,12 +232,19 @@ fn create_env_var(name: &str, value: &str) -> EnvVar {
     }
 }
 
-fn pod_env_variables(template: &Template, host: &str, session_id: &str) -> Vec<EnvVar> {
+// `SUBSTRATE_PLAYGROUND_SESSION` is only defined when the session is known upfront, i.e. not for warm pods
+fn pod_env_variables(template: &Template, host: &str, session_id: Option<&str>) -> Vec<EnvVar> {
     let mut envs = vec![
         create_env_var("SUBSTRATE_PLAYGROUND", ""),
-        create_env_var("SUBSTRATE_PLAYGROUND_SESSION", session_id),
         create_env_var("SUBSTRATE_PLAYGROUND_HOSTNAME", host),
+        create_env_var(
+            "SUBSTRATE_PLAYGROUND_EXPIRES_AT_FILE",
+            &format!("{}/{}", METADATA_PATH, EXPIRES_AT_FILE),
+        ),
     ];
+    if let Some(session_id) = session_id {
+        envs.push(create_env_var("SUBSTRATE_PLAYGROUND_SESSION", session_id));
+    }
     if let Some(mut template_envs) = template.runtime.as_ref().and_then(|r| {
         r.env.clone().map(|envs| {
             envs.iter()
@@ -94,88 +257,703 @@ fn pod_env_variables(template: &Template, host: &str, session_id: &str) -> Vec<E
     envs
 }
 
-// TODO detect when ingress is restarted, then re-sync theia sessions
-
-fn session_duration_annotation(duration: Duration) -> String {
-    let duration_min = duration.as_secs() / 60;
-    duration_min.to_string()
-}
-
-fn str_to_session_duration_minutes(str: &str) -> Result<Duration> {
-    Ok(Duration::from_secs(
-        str.parse::<u64>()
-            .map_err(|err| Error::Failure(err.into()))?
-            * 60,
-    ))
-}
-
 fn create_pod_annotations(
     template: &Template,
-    duration: &Duration,
+    resources: &Resources,
 ) -> Result<BTreeMap<String, String>> {
     let mut annotations = BTreeMap::new();
-    let s = serde_yaml::to_string(template).map_err(|err| Error::Failure(err.into()))?;
-    annotations.insert(TEMPLATE_ANNOTATION.to_string(), s);
+    let template = serde_yaml::to_string(template).map_err(|err| Error::Failure(err.into()))?;
+    // Quantities in the pod spec might be normalized, keep them as resolved
+    let resources = serde_yaml::to_string(resources).map_err(|err| Error::Failure(err.into()))?;
     annotations.insert(
-        SESSION_DURATION_ANNOTATION.to_string(),
-        session_duration_annotation(*duration),
+        SPEC_HASH_ANNOTATION.to_string(),
+        spec_hash(&template, &resources),
     );
+    annotations.insert(TEMPLATE_ANNOTATION.to_string(), template);
+    annotations.insert(RESOURCES_ANNOTATION.to_string(), resources);
     Ok(annotations)
 }
 
-fn create_pod(
+fn create_resource_requirements(resources: &Resources) -> ResourceRequirements {
+    let quantities = |quantities: &ResourceQuantities| {
+        Some(quantities.to_map())
+            .filter(|quantities| !quantities.is_empty())
+            .map(|quantities| {
+                quantities
+                    .into_iter()
+                    .map(|(name, quantity)| (name, Quantity(quantity)))
+                    .collect()
+            })
+    };
+    ResourceRequirements {
+        requests: quantities(&resources.requests),
+        limits: quantities(&resources.limits),
+    }
+}
+
+// Makes `owner` the controller of a dependent resource, so that it is garbage collected with it
+fn owner_reference<K: k8s_openapi::Resource + Meta>(owner: &K) -> Result<OwnerReference> {
+    Ok(OwnerReference {
+        api_version: K::API_VERSION.to_string(),
+        kind: K::KIND.to_string(),
+        name: owner.name(),
+        uid: owner
+            .meta()
+            .uid
+            .clone()
+            .ok_or(Error::MissingData("metadata#uid"))?,
+        controller: Some(true),
+        block_owner_deletion: Some(true),
+    })
+}
+
+// Runs `command` from its working directory
+fn create_pre_stop_handler(command: &Command) -> Handler {
+    Handler {
+        exec: Some(ExecAction {
+            command: Some(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                format!("cd {} && {}", command.working_directory, command.run),
+            ]),
+        }),
+        ..Default::default()
+    }
+}
+
+fn create_pod_spec(
     env: &Environment,
-    session_id: &str,
+    session_id: Option<&str>,
     template: &Template,
-    duration: &Duration,
+    resources: &Resources,
     pool_id: &str,
+) -> PodSpec {
+    let pre_stop = template
+        .runtime
+        .as_ref()
+        .and_then(|runtime| runtime.pre_stop.as_ref());
+    PodSpec {
+        affinity: Some(Affinity {
+            node_affinity: Some(NodeAffinity {
+                preferred_during_scheduling_ignored_during_execution: Some(vec![
+                    PreferredSchedulingTerm {
+                        weight: 100,
+                        preference: NodeSelectorTerm {
+                            match_expressions: Some(vec![NodeSelectorRequirement {
+                                key: NODE_POOL_LABEL.to_string(),
+                                operator: "In".to_string(),
+                                values: Some(vec![pool_id.to_string()]),
+                            }]),
+                            ..Default::default()
+                        },
+                    },
+                ]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        containers: vec![Container {
+            name: format!("{}-container", COMPONENT_VALUE),
+            image: Some(template.image.to_string()),
+            env: Some(pod_env_variables(template, &env.host, session_id)),
+            resources: Some(create_resource_requirements(resources)),
+            lifecycle: pre_stop.map(|command| Lifecycle {
+                pre_stop: Some(create_pre_stop_handler(command)),
+                ..Default::default()
+            }),
+            volume_mounts: Some(vec![VolumeMount {
+                name: METADATA_VOLUME.to_string(),
+                mount_path: METADATA_PATH.to_string(),
+                read_only: Some(true),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }],
+        // Expiry is kept up to date by the reconcile loop, see `Engine::reconcile`
+        volumes: Some(vec![Volume {
+            name: METADATA_VOLUME.to_string(),
+            downward_api: Some(DownwardAPIVolumeSource {
+                items: Some(vec![DownwardAPIVolumeFile {
+                    path: EXPIRES_AT_FILE.to_string(),
+                    field_ref: Some(ObjectFieldSelector {
+                        field_path: format!("metadata.annotations['{}']", EXPIRES_AT_ANNOTATION),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }]),
+        termination_grace_period_seconds: Some(if pre_stop.is_some() {
+            PRE_STOP_GRACE_PERIOD_SECONDS
+        } else {
+            1
+        }),
+        ..Default::default()
+    }
+}
+
+//
+// Mounts the `PersistentVolumeClaim` `claim_name` at `workspace.path`.
+// Empty workspaces are first seeded with the content of `image` at this path.
+//
+fn mount_workspace(
+    spec: &mut PodSpec,
+    image: &str,
+    claim_name: &str,
+    workspace: &WorkspaceDefaults,
+) {
+    let seed_path = "/mnt/workspace";
+    spec.volumes.get_or_insert_with(Vec::new).push(Volume {
+        name: WORKSPACE_VOLUME.to_string(),
+        persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+            claim_name: claim_name.to_string(),
+            read_only: None,
+        }),
+        ..Default::default()
+    });
+    // Make the volume writable by the image user
+    spec.security_context = Some(PodSecurityContext {
+        fs_group: Some(WORKSPACE_FS_GROUP),
+        ..Default::default()
+    });
+    spec.init_containers = Some(vec![Container {
+        name: format!("{}-init", WORKSPACE_COMPONENT_VALUE),
+        image: Some(image.to_string()),
+        command: Some(vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            format!(
+                "[ -n \"$(ls -A {seed})\" ] || cp -a {path}/. {seed}/",
+                seed = seed_path,
+                path = workspace.path
+            ),
+        ]),
+        volume_mounts: Some(vec![VolumeMount {
+            name: WORKSPACE_VOLUME.to_string(),
+            mount_path: seed_path.to_string(),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    }]);
+    for container in spec.containers.iter_mut() {
+        container
+            .volume_mounts
+            .get_or_insert_with(Vec::new)
+            .push(VolumeMount {
+                name: WORKSPACE_VOLUME.to_string(),
+                mount_path: workspace.path.clone(),
+                ..Default::default()
+            });
+    }
+}
+
+// `REPOSITORY` and `REF` as expected by `checkout_command`
+fn repository_env_variables(repository: &Repository) -> Vec<EnvVar> {
+    let mut envs = vec![create_env_var("REPOSITORY", &repository.clone_url())];
+    if let Some(reference) = &repository.reference {
+        envs.push(create_env_var("REF", reference));
+    }
+    envs
+}
+
+// Clones `$REPOSITORY` in `path`, then checks out `$REF` if set. Refs can be branches, tags, commits or e.g. `refs/pull/1/head`.
+fn checkout_command(path: &str) -> String {
+    format!(
+        "mkdir -p {path} && cd {path} && git clone \"$REPOSITORY\" . && if [ -n \"$REF\" ]; then git fetch origin \"$REF\" && git checkout FETCH_HEAD; fi",
+        path = path
+    )
+}
+
+//
+// Clones `repository` at `path` before other containers start, unless something is already there.
+// `path` is backed by an ephemeral volume, unless a workspace is mounted there.
+//
+fn clone_repository(spec: &mut PodSpec, repository: &Repository, path: &str) {
+    let mounted = spec.containers.iter().any(|container| {
+        container
+            .volume_mounts
+            .iter()
+            .flatten()
+            .any(|mount| mount.name == WORKSPACE_VOLUME)
+    });
+    if !mounted {
+        spec.volumes.get_or_insert_with(Vec::new).push(Volume {
+            name: WORKSPACE_VOLUME.to_string(),
+            empty_dir: Some(Default::default()),
+            ..Default::default()
+        });
+        for container in spec.containers.iter_mut() {
+            container
+                .volume_mounts
+                .get_or_insert_with(Vec::new)
+                .push(VolumeMount {
+                    name: WORKSPACE_VOLUME.to_string(),
+                    mount_path: path.to_string(),
+                    ..Default::default()
+                });
+        }
+    }
+    // Runs first, so that the workspace isn't seeded
+    spec.init_containers.get_or_insert_with(Vec::new).insert(
+        0,
+        Container {
+            name: format!("{}-clone", WORKSPACE_COMPONENT_VALUE),
+            image: Some(GIT_IMAGE.to_string()),
+            command: Some(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                format!(
+                    "if [ -n \"$(ls -A {path})\" ]; then echo \"{path} is not empty, skipping clone\"; else {checkout}; fi",
+                    path = path,
+                    checkout = checkout_command(path)
+                ),
+            ]),
+            env: Some(repository_env_variables(repository)),
+            volume_mounts: Some(vec![VolumeMount {
+                name: WORKSPACE_VOLUME.to_string(),
+                mount_path: path.to_string(),
+                ..Default::default()
+            }]),
+            // Cloned files must be owned by the image user
+            security_context: Some(SecurityContext {
+                run_as_user: Some(WORKSPACE_USER),
+                run_as_group: Some(WORKSPACE_FS_GROUP),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+}
+
+//
+// Requires pods mounting the workspace of `user_id` to run on the same node, as it is `ReadWriteOnce`.
+// Those pods are labelled with `USER_LABEL`: the first one matches itself and can be scheduled anywhere.
+//
+fn workspace_affinity(user_id: &str) -> PodAffinity {
+    let mut labels = BTreeMap::new();
+    labels.insert(APP_LABEL.to_string(), APP_VALUE.to_string());
+    labels.insert(USER_LABEL.to_string(), user_id.to_string());
+    PodAffinity {
+        required_during_scheduling_ignored_during_execution: Some(vec![PodAffinityTerm {
+            label_selector: Some(LabelSelector {
+                match_labels: Some(labels),
+                ..Default::default()
+            }),
+            topology_key: HOSTNAME_LABEL.to_string(),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    }
+}
+
+// `workspace` is the name of the claim to mount, along with its configuration
+fn create_pod(
+    env: &Environment,
+    session_id: &str,
+    session: &SessionSpec,
+    workspace: Option<(&str, &WorkspaceDefaults)>,
+    owner: OwnerReference,
 ) -> Result<Pod> {
     let mut labels = BTreeMap::new();
     labels.insert(APP_LABEL.to_string(), APP_VALUE.to_string());
     labels.insert(COMPONENT_LABEL.to_string(), COMPONENT_VALUE.to_string());
     labels.insert(OWNER_LABEL.to_string(), session_id.to_string());
 
+    let template = &session.template;
+    let mut spec = create_pod_spec(
+        env,
+        Some(session_id),
+        template,
+        &session.resources,
+        &session.pool_affinity,
+    );
+    if let Some((claim_name, workspace)) = workspace {
+        mount_workspace(&mut spec, &template.image, claim_name, workspace);
+        labels.insert(USER_LABEL.to_string(), session.user_id.clone());
+        if let Some(affinity) = spec.affinity.as_mut() {
+            affinity.pod_affinity = Some(workspace_affinity(&session.user_id));
+        }
+    }
+    if let Some(repository) = &session.repository {
+        let path = workspace.map_or(DEFAULT_WORKSPACE_PATH, |(_, workspace)| {
+            workspace.path.as_str()
+        });
+        clone_repository(&mut spec, repository, path);
+    }
+
     Ok(Pod {
         metadata: ObjectMeta {
             name: Some(pod_name(session_id)),
             labels: Some(labels),
-            annotations: Some(create_pod_annotations(template, duration)?),
+            annotations: Some(create_pod_annotations(template, &session.resources)?),
+            owner_references: Some(vec![owner]),
+            ..Default::default()
+        },
+        spec: Some(spec),
+        ..Default::default()
+    })
+}
+
+//
+// Creates an idle Pod started from `template`, owned by the `Template` itself.
+// It is later claimed by a session, see `Engine::claim_warm_pod`.
+//
+fn create_warm_pod(
+    env: &Environment,
+    template_id: &str,
+    template: &Template,
+    resources: &Resources,
+    pool_id: &str,
+    owner: OwnerReference,
+) -> Result<Pod> {
+    let mut labels = BTreeMap::new();
+    labels.insert(APP_LABEL.to_string(), APP_VALUE.to_string());
+    labels.insert(COMPONENT_LABEL.to_string(), COMPONENT_VALUE.to_string());
+    labels.insert(WARM_LABEL.to_string(), template_id.to_string());
+
+    Ok(Pod {
+        metadata: ObjectMeta {
+            generate_name: Some(format!("{}-warm-{}-", COMPONENT_VALUE, template_id)),
+            labels: Some(labels),
+            annotations: Some(create_pod_annotations(template, resources)?),
+            owner_references: Some(vec![owner]),
+            ..Default::default()
+        },
+        spec: Some(create_pod_spec(env, None, template, resources, pool_id)),
+        ..Default::default()
+    })
+}
+
+fn create_workspace_claim(user_id: &str, workspace: &WorkspaceDefaults) -> PersistentVolumeClaim {
+    let mut labels = BTreeMap::new();
+    labels.insert(APP_LABEL.to_string(), APP_VALUE.to_string());
+    labels.insert(
+        COMPONENT_LABEL.to_string(),
+        WORKSPACE_COMPONENT_VALUE.to_string(),
+    );
+    labels.insert(USER_LABEL.to_string(), user_id.to_string());
+    let mut annotations = BTreeMap::new();
+    annotations.insert(LAST_USED_ANNOTATION.to_string(), now_annotation());
+    let mut requests = BTreeMap::new();
+    requests.insert("storage".to_string(), Quantity(workspace.size.clone()));
+
+    PersistentVolumeClaim {
+        metadata: ObjectMeta {
+            name: Some(workspace_name(user_id)),
+            labels: Some(labels),
+            annotations: Some(annotations),
+            ..Default::default()
+        },
+        spec: Some(PersistentVolumeClaimSpec {
+            access_modes: Some(vec!["ReadWriteOnce".to_string()]),
+            resources: Some(ResourceRequirements {
+                requests: Some(requests),
+                ..Default::default()
+            }),
+            storage_class_name: workspace.storage_class.clone(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn claim_to_workspace(claim: &PersistentVolumeClaim) -> Option<Workspace> {
+    let user_id = claim.metadata.labels.as_ref()?.get(USER_LABEL)?.clone();
+    Some(Workspace {
+        user_id,
+        size: claim
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.resources.as_ref())
+            .and_then(|resources| resources.requests.as_ref())
+            .and_then(|requests| requests.get("storage"))
+            .map(|size| size.0.clone())
+            .unwrap_or_default(),
+        phase: claim
+            .status
+            .as_ref()
+            .and_then(|status| status.phase.clone())
+            .unwrap_or_else(|| "Pending".to_string()),
+        last_used: claim
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(LAST_USED_ANNOTATION))
+            .and_then(|secs| secs.parse().ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+    })
+}
+
+//
+// Creates a Pod logging a base64 encoded archive of the workspace of `user_id`, followed by its size in bytes.
+// Build outputs (`target`) are skipped. It runs next to sessions mounting the workspace, if any.
+//
+fn create_snapshot_pod(user_id: &str) -> Pod {
+    let mut labels = BTreeMap::new();
+    labels.insert(APP_LABEL.to_string(), APP_VALUE.to_string());
+    labels.insert(
+        COMPONENT_LABEL.to_string(),
+        WORKSPACE_COMPONENT_VALUE.to_string(),
+    );
+    labels.insert(USER_LABEL.to_string(), user_id.to_string());
+    let claim_name = workspace_name(user_id);
+    let mount_path = "/workspace";
+
+    Pod {
+        metadata: ObjectMeta {
+            generate_name: Some(format!("{}-snapshot-", claim_name)),
+            labels: Some(labels),
             ..Default::default()
         },
         spec: Some(PodSpec {
-            affinity: Some(Affinity {
-                node_affinity: Some(NodeAffinity {
-                    preferred_during_scheduling_ignored_during_execution: Some(vec![
-                        PreferredSchedulingTerm {
-                            weight: 100,
-                            preference: NodeSelectorTerm {
-                                match_expressions: Some(vec![NodeSelectorRequirement {
-                                    key: NODE_POOL_LABEL.to_string(),
-                                    operator: "In".to_string(),
-                                    values: Some(vec![pool_id.to_string()]),
-                                }]),
-                                ..Default::default()
-                            },
-                        },
-                    ]),
+            containers: vec![Container {
+                name: format!("{}-snapshot", WORKSPACE_COMPONENT_VALUE),
+                image: Some(SNAPSHOT_IMAGE.to_string()),
+                command: Some(vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    format!(
+                        "tar czf {0} --exclude=./target -C {1} . && base64 {0} && wc -c < {0}",
+                        "/tmp/snapshot.tgz", mount_path
+                    ),
+                ]),
+                volume_mounts: Some(vec![VolumeMount {
+                    name: WORKSPACE_VOLUME.to_string(),
+                    mount_path: mount_path.to_string(),
+                    read_only: Some(true),
                     ..Default::default()
+                }]),
+                ..Default::default()
+            }],
+            volumes: Some(vec![Volume {
+                name: WORKSPACE_VOLUME.to_string(),
+                persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+                    claim_name,
+                    read_only: Some(true),
                 }),
                 ..Default::default()
+            }]),
+            affinity: Some(Affinity {
+                pod_affinity: Some(workspace_affinity(user_id)),
+                ..Default::default()
             }),
-            containers: vec![Container {
-                name: format!("{}-container", COMPONENT_VALUE),
-                image: Some(template.image.to_string()),
-                env: Some(pod_env_variables(template, &env.host, session_id)),
+            restart_policy: Some("Never".to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+// The image of template `template_id` built as `tag`. The IDE image is suffixed with `-theia`.
+fn build_image(build: &BuildDefaults, template_id: &str, suffix: &str, tag: &str) -> String {
+    format!(
+        "{}/substrate-playground-template-{}{}:{}",
+        build.registry, template_id, suffix, tag
+    )
+}
+
+// A container running kaniko over `context` and `dockerfile` from the playground repository
+fn create_kaniko_container(name: &str, context: &str, dockerfile: &str, args: &str) -> Container {
+    Container {
+        name: format!("{}-{}", BUILD_COMPONENT_VALUE, name),
+        image: Some(KANIKO_IMAGE.to_string()),
+        command: Some(vec![
+            "/busybox/sh".to_string(),
+            "-c".to_string(),
+            format!(
+                "/kaniko/executor --context dir://{context} --dockerfile {playground}/templates/{dockerfile} --build-arg BASE_TEMPLATE_VERSION=$(grep BASE_TEMPLATE_VERSION {playground}/conf/templates/.env | cut -d '=' -f2) {args}",
+                context = context,
+                playground = BUILD_PLAYGROUND_PATH,
+                dockerfile = dockerfile,
+                args = args
+            ),
+        ]),
+        volume_mounts: Some(vec![
+            VolumeMount {
+                name: BUILD_VOLUME.to_string(),
+                mount_path: BUILD_PATH.to_string(),
                 ..Default::default()
-            }],
-            termination_grace_period_seconds: Some(1),
+            },
+            VolumeMount {
+                name: BUILD_SECRET_VOLUME.to_string(),
+                mount_path: "/kaniko/.docker".to_string(),
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    }
+}
+
+// A container cloning `repository` at `path` in the build volume
+fn create_build_clone_container(name: &str, repository: &Repository, path: &str) -> Container {
+    Container {
+        name: format!("{}-clone-{}", BUILD_COMPONENT_VALUE, name),
+        image: Some(GIT_IMAGE.to_string()),
+        command: Some(vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            checkout_command(path),
+        ]),
+        env: Some(repository_env_variables(repository)),
+        volume_mounts: Some(vec![VolumeMount {
+            name: BUILD_VOLUME.to_string(),
+            mount_path: BUILD_PATH.to_string(),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    }
+}
+
+//
+// Creates a Job building the images of template `template_id` from `repository`, owned by the `Template`.
+// Images are built as done by `make build-template`: first the template image, then the IDE image on top of it.
+//
+fn create_build_job(
+    id: &str,
+    template_id: &str,
+    repository: &Repository,
+    build: &BuildDefaults,
+    owner: OwnerReference,
+) -> Result<Job> {
+    let mut labels = BTreeMap::new();
+    labels.insert(APP_LABEL.to_string(), APP_VALUE.to_string());
+    labels.insert(
+        COMPONENT_LABEL.to_string(),
+        BUILD_COMPONENT_VALUE.to_string(),
+    );
+    let mut job_labels = labels.clone();
+    job_labels.insert(BUILD_TEMPLATE_LABEL.to_string(), template_id.to_string());
+
+    let template_image = build_image(build, template_id, "", id);
+    let image = build_image(build, template_id, "-theia", id);
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        REPOSITORY_ANNOTATION.to_string(),
+        serde_yaml::to_string(repository).map_err(|err| Error::Failure(err.into()))?,
+    );
+    annotations.insert(IMAGE_ANNOTATION.to_string(), image.clone());
+
+    let template_path = format!("{}/template", BUILD_PATH);
+    let mut volumes = vec![Volume {
+        name: BUILD_VOLUME.to_string(),
+        empty_dir: Some(Default::default()),
+        ..Default::default()
+    }];
+    // Credentials are expected by kaniko as `config.json`
+    volumes.push(match &build.push_secret {
+        Some(secret_name) => Volume {
+            name: BUILD_SECRET_VOLUME.to_string(),
+            secret: Some(SecretVolumeSource {
+                secret_name: Some(secret_name.clone()),
+                items: Some(vec![KeyToPath {
+                    key: ".dockerconfigjson".to_string(),
+                    path: "config.json".to_string(),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        None => Volume {
+            name: BUILD_SECRET_VOLUME.to_string(),
+            empty_dir: Some(Default::default()),
+            ..Default::default()
+        },
+    });
+
+    Ok(Job {
+        metadata: ObjectMeta {
+            name: Some(id.to_string()),
+            labels: Some(job_labels),
+            annotations: Some(annotations),
+            owner_references: Some(vec![owner]),
+            ..Default::default()
+        },
+        spec: Some(JobSpec {
+            // Failed builds are reported, not retried
+            backoff_limit: Some(0),
+            active_deadline_seconds: Some(BUILD_TIMEOUT.as_secs() as i64),
+            template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: Some(labels),
+                    ..Default::default()
+                }),
+                spec: Some(PodSpec {
+                    init_containers: Some(vec![
+                        create_build_clone_container("template", repository, &template_path),
+                        create_build_clone_container(
+                            "playground",
+                            &build.playground,
+                            BUILD_PLAYGROUND_PATH,
+                        ),
+                        create_kaniko_container(
+                            "template",
+                            &template_path,
+                            "Dockerfile.template",
+                            &format!("--destination {}", template_image),
+                        ),
+                    ]),
+                    containers: vec![create_kaniko_container(
+                        "theia",
+                        &format!("{}/templates", BUILD_PLAYGROUND_PATH),
+                        "Dockerfile.theia-template",
+                        &format!(
+                            "--build-arg TEMPLATE_IMAGE={} --destination {}",
+                            template_image, image
+                        ),
+                    )],
+                    volumes: Some(volumes),
+                    restart_policy: Some("Never".to_string()),
+                    ..Default::default()
+                }),
+            },
             ..Default::default()
         }),
         ..Default::default()
     })
 }
 
-fn create_service(session_id: &str, template: &Template) -> Service {
+fn job_to_build(job: &Job) -> Option<Build> {
+    let annotations = job.metadata.annotations.as_ref()?;
+    let status = job.status.clone().unwrap_or_default();
+    let phase = if status.succeeded.unwrap_or_default() > 0 {
+        Phase::Succeeded
+    } else if status.failed.unwrap_or_default() > 0 {
+        Phase::Failed
+    } else if status.active.unwrap_or_default() > 0 {
+        Phase::Running
+    } else {
+        Phase::Pending
+    };
+    let time = |time: Option<Time>| {
+        time.map(|time| UNIX_EPOCH + Duration::from_secs(time.0.timestamp() as u64))
+    };
+    Some(Build {
+        id: job.name(),
+        template: job
+            .metadata
+            .labels
+            .as_ref()?
+            .get(BUILD_TEMPLATE_LABEL)?
+            .clone(),
+        repository: serde_yaml::from_str(annotations.get(REPOSITORY_ANNOTATION)?).ok()?,
+        image: annotations.get(IMAGE_ANNOTATION)?.clone(),
+        phase,
+        message: status
+            .conditions
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|condition| condition.message)
+            .next_back()
+            .unwrap_or_default(),
+        start_time: time(status.start_time),
+        completion_time: time(status.completion_time),
+    })
+}
+
+fn create_service(session_id: &str, template: &Template, owner: OwnerReference) -> Service {
     let mut labels = BTreeMap::new();
     labels.insert(APP_LABEL.to_string(), APP_VALUE.to_string());
     labels.insert(COMPONENT_LABEL.to_string(), COMPONENT_VALUE.to_string());
@@ -211,6 +989,7 @@ fn create_service(session_id: &str, template: &Template) -> Service {
         metadata: ObjectMeta {
             name: Some(service_name(session_id)),
             labels: Some(labels),
+            owner_references: Some(vec![owner]),
             ..Default::default()
         },
         spec: Some(ServiceSpec {
@@ -250,20 +1029,48 @@ fn create_ingress_paths(service_name: String, template: &Template) -> Vec<HTTPIn
     paths
 }
 
-fn subdomain(host: &str, session_id: &str) -> String {
-    format!("{}.{}", session_id, host)
-}
-
-async fn config() -> Result<Config> {
-    Config::from_kubeconfig(&KubeConfigOptions::default())
-        .await
-        .or_else(|_| Config::from_cluster_env())
-        .map_err(|err| Error::Failure(err.into()))
+//
+// Creates the Ingress routing the session subdomain to its service.
+// Annotations and TLS configuration are inherited from the shared ingress.
+//
+fn create_ingress(
+    engine: &Engine,
+    session_id: &str,
+    template: &Template,
+    owner: OwnerReference,
+) -> Ingress {
+    let mut labels = BTreeMap::new();
+    labels.insert(APP_LABEL.to_string(), APP_VALUE.to_string());
+    labels.insert(COMPONENT_LABEL.to_string(), COMPONENT_VALUE.to_string());
+    labels.insert(OWNER_LABEL.to_string(), session_id.to_string());
+
+    Ingress {
+        metadata: ObjectMeta {
+            name: Some(ingress_name(session_id)),
+            labels: Some(labels),
+            annotations: Some(engine.ingress_annotations.clone()),
+            owner_references: Some(vec![owner]),
+            ..Default::default()
+        },
+        spec: Some(IngressSpec {
+            rules: Some(vec![IngressRule {
+                host: Some(subdomain(&engine.env.host, session_id)),
+                http: Some(HTTPIngressRuleValue {
+                    paths: create_ingress_paths(service_name(session_id), template),
+                }),
+            }]),
+            tls: engine.ingress_tls.clone(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
 }
 
-async fn new_client() -> Result<Client> {
-    let config = config().await?;
-    Client::try_from(config).map_err(|err| Error::Failure(err.into()))
+async fn config() -> Result<Config> {
+    Config::from_kubeconfig(&KubeConfigOptions::default())
+        .await
+        .or_else(|_| Config::from_cluster_env())
+        .map_err(Error::K8s)
 }
 
 // ConfigMap utilities
@@ -277,7 +1084,7 @@ async fn get_config_map(
     config_map_api
         .get(name)
         .await
-        .map_err(|err| Error::Failure(err.into()))
+        .map_err(Error::K8s)
         .and_then(|o| o.data.ok_or(Error::MissingData("config map")))
 }
 
@@ -305,7 +1112,7 @@ async fn add_config_map_value(
     config_map_api
         .patch(name, &params, &patch)
         .await
-        .map_err(|err| Error::Failure(err.into()))?;
+        .map_err(Error::K8s)?;
     Ok(())
 }
 
@@ -333,7 +1140,7 @@ async fn delete_config_map_value(
     config_map_api
         .patch(name, &params, &patch)
         .await
-        .map_err(|err| Error::Failure(err.into()))?;
+        .map_err(Error::K8s)?;
     Ok(())
 }
 
@@ -341,27 +1148,259 @@ async fn get_templates(client: Client, namespace: &str) -> Result<BTreeMap<Strin
     get_config_map(client, namespace, TEMPLATES_CONFIG_MAP).await
 }
 
-async fn list_users(client: Client, namespace: &str) -> Result<BTreeMap<String, String>> {
-    get_config_map(client, namespace, USERS_CONFIG_MAP).await
+// Parses and validates a `TemplateResource`. Returns the matching `Template` if valid, and the computed status
+fn resource_to_template(resource: &TemplateResource) -> (Option<Template>, TemplateStatus) {
+    match serde_json::from_value::<Template>(resource.spec.clone()) {
+        Ok(template) => {
+            let problems = template.validate();
+            let valid = problems.is_empty();
+            (
+                Some(template).filter(|_| valid),
+                TemplateStatus { valid, problems },
+            )
+        }
+        Err(err) => (
+            None,
+            TemplateStatus {
+                valid: false,
+                problems: vec![format!("failed to parse: {}", err)],
+            },
+        ),
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Environment {
-    pub secured: bool,
-    pub host: String,
-    pub namespace: String,
+// The version of the current `spec` of `resource`, incremented by the API server on each change of `spec`
+fn template_version(resource: &TemplateResource) -> u64 {
+    resource.metadata.generation.unwrap_or_default() as u64
 }
 
-#[derive(Serialize, Debug, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct Configuration {
-    pub github_client_id: String,
-    pub session: SessionDefaults,
+// `versions` with `template` recorded as `version` if newer than the latest one, keeping the `size` latest
+fn record_version(
+    mut versions: Vec<TemplateVersion>,
+    version: u64,
+    template: &Template,
+    size: usize,
+) -> Vec<TemplateVersion> {
+    if !matches!(versions.last(), Some(latest) if latest.version >= version) {
+        versions.push(TemplateVersion {
+            version,
+            template: template.clone(),
+        });
+    }
+    if versions.len() > size {
+        versions.drain(..versions.len() - size);
+    }
+    versions
 }
 
-#[derive(Clone)]
-pub struct Secrets {
-    pub github_client_secret: String,
+// The name of the ConfigMap keeping versions of template `id`
+fn template_versions_name(id: &str) -> String {
+    format!("template-{}-versions", id)
+}
+
+// Parses versions kept in a ConfigMap, oldest first. Entries failing to parse are skipped.
+fn config_map_to_versions(config_map: &ConfigMap) -> Vec<TemplateVersion> {
+    let mut versions: Vec<TemplateVersion> = config_map
+        .data
+        .iter()
+        .flatten()
+        .filter_map(
+            |(version, template)| match (version.parse(), serde_yaml::from_str(template)) {
+                (Ok(version), Ok(template)) => Some(TemplateVersion { version, template }),
+                _ => {
+                    error!("Invalid version {} in {}", version, config_map.name());
+                    None
+                }
+            },
+        )
+        .collect();
+    versions.sort_by_key(|version| version.version);
+    versions
+}
+
+// Creates a ConfigMap keeping `versions` of template `id`, one YAML `Template` per version, owned by the template
+fn create_versions_config_map(
+    id: &str,
+    versions: &[TemplateVersion],
+    owner: OwnerReference,
+) -> Result<ConfigMap> {
+    let mut labels = BTreeMap::new();
+    labels.insert(APP_LABEL.to_string(), APP_VALUE.to_string());
+    labels.insert(
+        COMPONENT_LABEL.to_string(),
+        TEMPLATE_VERSIONS_COMPONENT_VALUE.to_string(),
+    );
+    let data = versions
+        .iter()
+        .map(|version| {
+            serde_yaml::to_string(&version.template)
+                .map(|template| (version.version.to_string(), template))
+                .map_err(|err| Error::Failure(err.into()))
+        })
+        .collect::<Result<BTreeMap<String, String>>>()?;
+    Ok(ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(template_versions_name(id)),
+            labels: Some(labels),
+            owner_references: Some(vec![owner]),
+            ..Default::default()
+        },
+        data: Some(data),
+        ..Default::default()
+    })
+}
+
+async fn update_template_status(
+    template_api: &Api<TemplateResource>,
+    id: &str,
+    status: &TemplateStatus,
+) -> Result<()> {
+    let patch: Patch<serde_json::Value> = Patch::Merge(json!({ "status": status }));
+    template_api
+        .patch_status(id, &PatchParams::default(), &patch)
+        .await
+        .map_err(Error::K8s)?;
+    Ok(())
+}
+
+///
+/// Creates a `Template` Custom Resource for each entry of the `playground-templates` ConfigMap.
+/// Returns the number of imported templates.
+///
+pub async fn import_templates_config_map() -> Result<usize> {
+    let config = config().await?;
+    let namespace = config.default_ns.clone();
+    let client = Client::try_from(config).map_err(Error::K8s)?;
+    let template_api: Api<TemplateResource> = Api::namespaced(client.clone(), &namespace);
+
+    let mut count = 0;
+    for (id, value) in get_templates(client, &namespace).await? {
+        let template: Template = match serde_yaml::from_str(&value) {
+            Ok(template) => template,
+            Err(err) => {
+                error!("Error while parsing template {}: {}", id, err);
+                continue;
+            }
+        };
+        let resource =
+            TemplateResource::new(&id, &template).map_err(|err| Error::Failure(err.into()))?;
+        template_api
+            .create(&PostParams::default(), &resource)
+            .await
+            .map_err(Error::K8s)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+///
+/// Removes rules of the shared ingress routing to session services, left over from before sessions had their own Ingress.
+/// Returns the number of removed rules.
+///
+pub async fn prune_ingress_rules() -> Result<usize> {
+    let config = config().await?;
+    let namespace = config.default_ns.clone();
+    let client = Client::try_from(config).map_err(Error::K8s)?;
+    let ingress_api: Api<Ingress> = Api::namespaced(client, &namespace);
+
+    let mut ingress = match get_optional(&ingress_api, INGRESS_NAME).await? {
+        Some(ingress) => ingress,
+        None => return Ok(0),
+    };
+    let mut spec = ingress.spec.take().ok_or(Error::MissingData("spec"))?;
+    let prefix = service_name("");
+    let rules = spec.rules.take().unwrap_or_default();
+    let count = rules.len();
+    let rules: Vec<IngressRule> = rules
+        .into_iter()
+        .filter(|rule| {
+            let paths = rule
+                .http
+                .as_ref()
+                .map(|http| http.paths.as_slice())
+                .unwrap_or_default();
+            paths.is_empty()
+                || !paths
+                    .iter()
+                    .all(|path| path.backend.service_name.starts_with(&prefix))
+        })
+        .collect();
+    let pruned = count - rules.len();
+    if pruned > 0 {
+        spec.rules.replace(rules);
+        ingress.spec.replace(spec);
+        ingress_api
+            .replace(INGRESS_NAME, &PostParams::default(), &ingress)
+            .await
+            .map_err(Error::K8s)?;
+    }
+    Ok(pruned)
+}
+
+fn yaml_to_user(s: &str) -> Result<User> {
+    let user_configuration: UserConfiguration =
+        serde_yaml::from_str(s).map_err(|err| Error::Failure(err.into()))?;
+    Ok(user_configuration.into())
+}
+
+/// A `UserStore` backed by the `playground-users` ConfigMap, where each user is stored as a YAML string
+pub struct ConfigMapUserStore {
+    client: Client,
+    namespace: String,
+}
+
+impl ConfigMapUserStore {
+    pub fn new(client: Client, namespace: &str) -> Self {
+        ConfigMapUserStore {
+            client,
+            namespace: namespace.to_string(),
+        }
+    }
+
+    /// Creates a `ConfigMapUserStore` targeting the default namespace of the current k8s context
+    pub async fn from_context() -> Result<Self> {
+        let config = config().await?;
+        let namespace = config.default_ns.clone();
+        let client = Client::try_from(config).map_err(Error::K8s)?;
+        Ok(Self::new(client, &namespace))
+    }
+}
+
+#[async_trait]
+impl UserStore for ConfigMapUserStore {
+    async fn get(&self, id: &str) -> Result<Option<User>> {
+        let users = get_config_map(self.client.clone(), &self.namespace, USERS_CONFIG_MAP).await?;
+
+        match users.get(id).map(|user| yaml_to_user(user)) {
+            Some(user) => user.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn list(&self) -> Result<BTreeMap<String, User>> {
+        get_config_map(self.client.clone(), &self.namespace, USERS_CONFIG_MAP)
+            .await?
+            .into_iter()
+            .map(|(k, v)| Ok((k, yaml_to_user(&v)?)))
+            .collect::<Result<BTreeMap<String, User>>>()
+    }
+
+    async fn put(&self, id: &str, user: User) -> Result<()> {
+        add_config_map_value(
+            self.client.clone(),
+            &self.namespace,
+            USERS_CONFIG_MAP,
+            id,
+            serde_yaml::to_string(&user)
+                .map_err(|err| Error::Failure(err.into()))?
+                .as_str(),
+        )
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        delete_config_map_value(self.client.clone(), &self.namespace, USERS_CONFIG_MAP, id).await
+    }
 }
 
 #[derive(Clone)]
@@ -369,53 +1408,107 @@ pub struct Engine {
     pub env: Environment,
     pub configuration: Configuration,
     pub secrets: Secrets,
+    client: Client,
+    users: Arc<dyn UserStore>,
+    ingress_annotations: BTreeMap<String, String>,
+    ingress_tls: Option<Vec<IngressTLS>>,
+    // Reads are served from these caches, kept up to date by `spawn_watchers`
+    templates: Cache<TemplateResource>,
+    sessions: Cache<SessionResource>,
+    pods: Cache<Pod>,
+    services: Cache<Service>,
+    ingresses: Cache<Ingress>,
+    nodes: Cache<Node>,
+    builds: Cache<Job>,
+    template_versions: Cache<ConfigMap>,
+    // Held while creating sessions, so that pools capacity is not exceeded by concurrent requests
+    creating: Arc<AsyncMutex<()>>,
+    // Held while recording template versions, so that concurrent recordings don't drop each other's
+    recording: Arc<AsyncMutex<()>>,
 }
 
 impl Engine {
+    const RECONCILE_PERIOD: Duration = Duration::from_secs(5);
+
     pub async fn new() -> Result<Self> {
         let config = config().await?;
         let namespace = config.clone().default_ns.to_string();
-        let client = Client::try_from(config).map_err(|err| Error::Failure(err.into()))?;
+        let client = Client::try_from(config).map_err(Error::K8s)?;
         let ingress_api: Api<Ingress> = Api::namespaced(client.clone(), &namespace);
-        let secured = if let Ok(ingress) = ingress_api.get(INGRESS_NAME).await {
-            ingress
-                .spec
-                .ok_or(Error::MissingData("spec"))?
-                .tls
-                .is_some()
-        } else {
-            false
-        };
+        let (host, ingress_annotations, ingress_tls) =
+            if let Ok(ingress) = ingress_api.get(INGRESS_NAME).await {
+                let spec = ingress.spec.ok_or(Error::MissingData("spec"))?;
+                let host = spec
+                    .rules
+                    .ok_or(Error::MissingData("spec#rules"))?
+                    .first()
+                    .ok_or(Error::MissingData("spec#rules[0]"))?
+                    .host
+                    .as_ref()
+                    .ok_or(Error::MissingData("spec#rules[0]#host"))?
+                    .clone();
+                (
+                    host,
+                    ingress.metadata.annotations.unwrap_or_default(),
+                    spec.tls,
+                )
+            } else {
+                ("localhost".to_string(), BTreeMap::new(), None)
+            };
+        let secured = ingress_tls.is_some();
 
-        let host = if let Ok(ingress) = ingress_api.get(INGRESS_NAME).await {
-            ingress
-                .spec
-                .ok_or(Error::MissingData("spec"))?
-                .rules
-                .ok_or(Error::MissingData("spec#rules"))?
-                .first()
-                .ok_or(Error::MissingData("spec#rules[0]"))?
-                .host
-                .as_ref()
-                .ok_or(Error::MissingData("spec#rules[0]#host"))?
-                .clone()
-        } else {
-            "localhost".to_string()
+        // Users are stored in a local file if `PLAYGROUND_USERS_FILE` is set, in a ConfigMap otherwise
+        let users: Arc<dyn UserStore> = match env::var("PLAYGROUND_USERS_FILE") {
+            Ok(path) => Arc::new(FileUserStore::open(path)?),
+            Err(_) => Arc::new(ConfigMapUserStore::new(client.clone(), &namespace)),
         };
 
-        // Retrieve 'static' configuration from Env variables
-        let github_client_id =
-            env::var("GITHUB_CLIENT_ID").map_err(|_| Error::MissingData("GITHUB_CLIENT_ID"))?;
-        let github_client_secret =
-            env::var("GITHUB_CLIENT_SECRET").map_err(|_| Error::MissingData("GITHUB_CLIENT_ID"))?;
-        let session_default_duration = env::var("SESSION_DEFAULT_DURATION")
-            .map_err(|_| Error::MissingData("SESSION_DEFAULT_DURATION"))?;
-        let session_max_duration = env::var("SESSION_DEFAULT_DURATION")
-            .map_err(|_| Error::MissingData("SESSION_MAX_DURATION"))?;
-        let session_default_pool_affinity = env::var("SESSION_DEFAULT_POOL_AFFINITY")
-            .map_err(|_| Error::MissingData("SESSION_DEFAULT_POOL_AFFINITY"))?;
-        let session_default_max_per_node = env::var("SESSION_DEFAULT_MAX_PER_NODE")
-            .map_err(|_| Error::MissingData("SESSION_DEFAULT_MAX_PER_NODE"))?;
+        let session_selector = ListParams::default().labels(&format!(
+            "{}={},{}={}",
+            APP_LABEL, APP_VALUE, COMPONENT_LABEL, COMPONENT_VALUE
+        ));
+        let templates = Cache::new(
+            Api::namespaced(client.clone(), &namespace),
+            ListParams::default(),
+        )
+        .await?;
+        let sessions = Cache::new(
+            Api::namespaced(client.clone(), &namespace),
+            ListParams::default(),
+        )
+        .await?;
+        let pods = Cache::new(
+            Api::namespaced(client.clone(), &namespace),
+            session_selector.clone(),
+        )
+        .await?;
+        let services = Cache::new(
+            Api::namespaced(client.clone(), &namespace),
+            session_selector.clone(),
+        )
+        .await?;
+        let ingresses = Cache::new(
+            Api::namespaced(client.clone(), &namespace),
+            session_selector,
+        )
+        .await?;
+        let nodes = Cache::new(Api::all(client.clone()), ListParams::default()).await?;
+        let builds = Cache::new(
+            Api::namespaced(client.clone(), &namespace),
+            ListParams::default().labels(&format!(
+                "{}={},{}={}",
+                APP_LABEL, APP_VALUE, COMPONENT_LABEL, BUILD_COMPONENT_VALUE
+            )),
+        )
+        .await?;
+        let template_versions = Cache::new(
+            Api::namespaced(client.clone(), &namespace),
+            ListParams::default().labels(&format!(
+                "{}={},{}={}",
+                APP_LABEL, APP_VALUE, COMPONENT_LABEL, TEMPLATE_VERSIONS_COMPONENT_VALUE
+            )),
+        )
+        .await?;
 
         Ok(Engine {
             env: Environment {
@@ -423,62 +1516,62 @@ impl Engine {
                 host,
                 namespace: namespace.clone(),
             },
-            configuration: Configuration {
-                github_client_id,
-                session: SessionDefaults {
-                    duration: str_to_session_duration_minutes(&session_default_duration)?,
-                    max_duration: str_to_session_duration_minutes(&session_max_duration)?,
-                    pool_affinity: session_default_pool_affinity,
-                    max_sessions_per_pod: session_default_max_per_node
-                        .parse()
-                        .map_err(|err: ParseIntError| Error::Failure(err.into()))?,
-                },
-            },
-            secrets: Secrets {
-                github_client_secret,
-            },
+            configuration: Configuration::from_env()?,
+            secrets: Secrets::from_env()?,
+            client,
+            users,
+            ingress_annotations,
+            ingress_tls,
+            templates,
+            sessions,
+            pods,
+            services,
+            ingresses,
+            nodes,
+            builds,
+            template_versions,
+            creating: Arc::new(AsyncMutex::new(())),
+            recording: Arc::new(AsyncMutex::new(())),
         })
     }
 
-    // Creates a Session from a Pod annotations
-    fn pod_to_session(self, env: &Environment, pod: &Pod) -> Result<Session> {
-        let labels = pod
-            .metadata
-            .labels
-            .clone()
-            .ok_or(Error::MissingData("pod#metadata#labels"))?;
-        let unknown = "UNKNOWN OWNER".to_string();
-        let username = labels.get(OWNER_LABEL).unwrap_or(&unknown);
-        let annotations = &pod
-            .metadata
-            .annotations
-            .clone()
-            .ok_or(Error::MissingData("pod#metadata#annotations"))?;
-        let template = serde_yaml::from_str(
-            &annotations
-                .get(TEMPLATE_ANNOTATION)
-                .ok_or(Error::MissingData("template"))?,
-        )
-        .map_err(|err| Error::Failure(err.into()))?;
-        let duration = str_to_session_duration_minutes(
-            annotations
-                .get(SESSION_DURATION_ANNOTATION)
-                .ok_or(Error::MissingData("template#session_duration"))?,
-        )?;
-
-        Ok(Session {
-            user_id: username.clone(),
-            template,
-            url: subdomain(&env.host, &username),
-            pod: Self::pod_to_details(self, &pod.clone())?,
+    // Creates a Session from a `SessionResource`, relying on the status reported by the reconcile loop
+    fn resource_to_session(&self, resource: &SessionResource) -> Session {
+        let status = resource.status.clone();
+        let start_time = status
+            .as_ref()
+            .and_then(|status| status.start_time)
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs as u64));
+        let duration = Duration::from_secs(resource.spec.duration * 60);
+        Session {
+            id: resource.name(),
+            user_id: resource.spec.user_id.clone(),
+            template_id: resource.spec.template_id.clone(),
+            template_version: resource.spec.template_version,
+            template: resource.spec.template.clone(),
+            url: subdomain(&self.env.host, &resource.name()),
+            pod: types::Pod {
+                phase: status
+                    .as_ref()
+                    .map_or(Phase::Pending, |status| status.phase.clone()),
+                reason: status
+                    .as_ref()
+                    .map(|status| status.reason.clone())
+                    .unwrap_or_default(),
+                message: status
+                    .as_ref()
+                    .map(|status| status.message.clone())
+                    .unwrap_or_default(),
+                start_time,
+                container: status.as_ref().and_then(|status| status.container.clone()),
+            },
             duration,
-            node: pod
-                .clone()
-                .spec
-                .ok_or(Error::MissingData("pod#spec"))?
-                .node_name
-                .ok_or(Error::MissingData("pod#spec#node_name"))?,
-        })
+            node: status.and_then(|status| status.node).unwrap_or_default(),
+            pool_affinity: resource.spec.pool_affinity.clone(),
+            resources: resource.spec.resources.clone(),
+            remaining: types::remaining_time(start_time, duration),
+            repository: resource.spec.repository.clone(),
+        }
     }
 
     fn nodes_to_pool(self, id: String, nodes: Vec<Node>) -> Result<Pool> {
@@ -494,10 +1587,10 @@ impl Engine {
         let unknown = "unknown".to_string();
         let instance_type = labels.get(INSTANCE_TYPE_LABEL).unwrap_or(&local);
 
-        Ok(Pool {
-            name: id,
-            instance_type: Some(instance_type.clone()),
-            nodes: nodes
+        Ok(Pool::new(
+            id,
+            Some(instance_type.clone()),
+            nodes
                 .iter()
                 .map(|node| crate::types::Node {
                     hostname: node
@@ -510,7 +1603,8 @@ impl Engine {
                         .clone(),
                 })
                 .collect(),
-        })
+            self.configuration.session.max_sessions_per_pod,
+        ))
     }
 
     fn container_status_to_container_status(
@@ -530,7 +1624,7 @@ impl Engine {
                     }
                 })
                 .unwrap_or(ContainerPhase::Unknown),
-            reason: state.clone().and_then(|s| {
+            reason: state.and_then(|s| {
                 s.waiting
                     .as_ref()
                     .and_then(|s| s.reason.clone())
@@ -545,11 +1639,24 @@ impl Engine {
         }
     }
 
-    fn pod_to_details(self, pod: &Pod) -> Result<types::Pod> {
-        let status = pod.status.as_ref().ok_or(Error::MissingData("status"))?;
+    fn pod_to_status(self, pod: &Pod) -> Result<SessionStatus> {
+        let status = match pod.status.as_ref() {
+            Some(status) => status,
+            // Newly created pods don't have a status yet
+            None => {
+                return Ok(SessionStatus {
+                    phase: Phase::Pending,
+                    reason: "".to_string(),
+                    message: "".to_string(),
+                    start_time: None,
+                    node: None,
+                    container: None,
+                })
+            }
+        };
         let container_statuses = status.clone().container_statuses;
         let container_status = container_statuses.as_ref().and_then(|v| v.first());
-        Ok(types::Pod {
+        Ok(SessionStatus {
             phase: Phase::from_str(
                 &status
                     .clone()
@@ -559,340 +1666,804 @@ impl Engine {
             .map_err(|err| Error::Failure(err.into()))?,
             reason: status.clone().reason.unwrap_or_else(|| "".to_string()),
             message: status.clone().message.unwrap_or_else(|| "".to_string()),
-            start_time: status.clone().start_time.map(|dt| dt.0.into()),
+            // Claimed warm pods start along with their session
+            start_time: pod
+                .metadata
+                .annotations
+                .as_ref()
+                .and_then(|annotations| annotations.get(CLAIMED_ANNOTATION))
+                .and_then(|claimed| claimed.parse().ok())
+                .or_else(|| status.clone().start_time.map(|dt| dt.0.timestamp())),
+            node: pod.spec.as_ref().and_then(|spec| spec.node_name.clone()),
             container: container_status.map(|c| self.container_status_to_container_status(c)),
         })
     }
 
-    fn yaml_to_user(self, s: &str) -> Result<User> {
-        let user_configuration: UserConfiguration =
-            serde_yaml::from_str(s).map_err(|err| Error::Failure(err.into()))?;
-        Ok(User {
-            admin: user_configuration.admin,
-            pool_affinity: user_configuration.pool_affinity,
-            can_customize_duration: user_configuration.can_customize_duration,
-            can_customize_pool_affinity: user_configuration.can_customize_pool_affinity,
+    ///
+    /// Spawns tasks keeping all caches up to date, by watching the underlying resources.
+    ///
+    pub fn spawn_watchers(self) -> Vec<JoinHandle<()>> {
+        vec![
+            tokio::spawn(self.templates.watch()),
+            tokio::spawn(self.sessions.watch()),
+            tokio::spawn(self.pods.watch()),
+            tokio::spawn(self.services.watch()),
+            tokio::spawn(self.ingresses.watch()),
+            tokio::spawn(self.nodes.watch()),
+            tokio::spawn(self.builds.watch()),
+            tokio::spawn(self.template_versions.watch()),
+        ]
+    }
+
+    ///
+    /// Spawns a task periodically converging all `Session`s to their desired state.
+    ///
+    /// Changes of the underlying Pods are promptly reported back in each `Session` status.
+    ///
+    pub fn spawn_reconciler(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut pod_events = self.pods.subscribe();
+            loop {
+                if let Err(err) = self.reconcile_all().await {
+                    error!("Failed to reconcile sessions: {}", err);
+                }
+                // Pod changes are reported right away
+                tokio::select! {
+                    _ = sleep(Engine::RECONCILE_PERIOD) => {},
+                    _ = pod_events.recv() => {},
+                }
+            }
         })
     }
 
-    pub async fn list_templates(self) -> Result<BTreeMap<String, Template>> {
-        let client = new_client().await?;
+    async fn reconcile_all(&self) -> Result<()> {
+        for session in self.sessions.list()? {
+            if let Err(err) = self.reconcile(self.client.clone(), &session).await {
+                error!("Failed to reconcile session {}: {}", session.name(), err);
+            }
+        }
+        self.reconcile_templates().await?;
+        self.reconcile_warm_pods().await?;
+        self.reconcile_builds().await
+    }
 
-        Ok(get_templates(client, &self.env.namespace)
-            .await?
-            .into_iter()
-            .filter_map(|(k, v)| {
-                if let Ok(template) = serde_yaml::from_str(&v) {
-                    Some((k, template))
-                } else {
-                    error!("Error while parsing template {}", k);
-                    None
+    // Versions kept of template `id`, oldest first
+    fn kept_template_versions(&self, id: &str) -> Result<Vec<TemplateVersion>> {
+        Ok(self
+            .template_versions
+            .get(&template_versions_name(id))?
+            .map(|config_map| config_map_to_versions(&config_map))
+            .unwrap_or_default())
+    }
+
+    //
+    // Records the version of `resource` if valid and newer than the latest recorded one.
+    // Versions are kept in a ConfigMap owned by each template, so that they survive changes of its status.
+    // They are recorded right after each update, so that successive updates are not missed.
+    //
+    async fn record_template_version(&self, resource: &TemplateResource) -> Result<()> {
+        let template = match resource_to_template(resource).0 {
+            Some(template) => template,
+            None => return Ok(()),
+        };
+        let _recording = self.recording.lock().await;
+        let id = resource.name();
+        let name = template_versions_name(&id);
+        let config_map_api: Api<ConfigMap> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        // The cache might lag behind previous recordings
+        let existing = get_optional(&config_map_api, &name).await?;
+        let kept = existing
+            .as_ref()
+            .map(config_map_to_versions)
+            .unwrap_or_default();
+        let version = template_version(resource);
+        if matches!(kept.last(), Some(latest) if latest.version >= version) {
+            return Ok(());
+        }
+        let versions = record_version(
+            kept,
+            version,
+            &template,
+            self.configuration.template_history_size,
+        );
+        let mut config_map =
+            create_versions_config_map(&id, &versions, owner_reference(resource)?)?;
+        match existing {
+            Some(existing) => {
+                // Concurrent updates fail, as the version is checked
+                config_map.metadata.resource_version = existing.metadata.resource_version;
+                config_map_api
+                    .replace(&name, &PostParams::default(), &config_map)
+                    .await
+                    .map_err(Error::K8s)?;
+            }
+            None => {
+                config_map_api
+                    .create(&PostParams::default(), &config_map)
+                    .await
+                    .map_err(Error::K8s)?;
+            }
+        }
+        info!("Recorded version {} of template {}", version, id);
+        Ok(())
+    }
+
+    //
+    // Reports problems of templates in their status, and records their versions missed so far.
+    //
+    async fn reconcile_templates(&self) -> Result<()> {
+        let template_api: Api<TemplateResource> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        for resource in self.templates.list()? {
+            let id = resource.name();
+            let (template, status) = resource_to_template(&resource);
+            if resource.status.as_ref() != Some(&status) {
+                // Surface problems to admins via the status subresource
+                if let Err(err) = update_template_status(&template_api, &id, &status).await {
+                    error!("Failed to update status of template {}: {}", id, err);
                 }
-            })
-            .collect::<BTreeMap<String, Template>>())
+            }
+            if template.is_none() {
+                continue;
+            }
+            let kept = self.kept_template_versions(&id)?;
+            if matches!(kept.last(), Some(latest) if latest.version >= template_version(&resource))
+            {
+                continue;
+            }
+            if let Err(err) = self.record_template_version(&resource).await {
+                error!("Failed to record version of template {}: {}", id, err);
+            }
+        }
+        Ok(())
     }
 
-    pub async fn get_user(&self, id: &str) -> Result<Option<User>> {
-        let client = new_client().await?;
+    // Sets images of successful builds on their template, once
+    async fn reconcile_builds(&self) -> Result<()> {
+        let template_api: Api<TemplateResource> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        let job_api: Api<Job> = Api::namespaced(self.client.clone(), &self.env.namespace);
+        for job in self.builds.list()? {
+            let applied = matches!(&job.metadata.annotations, Some(annotations) if annotations.contains_key(APPLIED_ANNOTATION));
+            let build = match job_to_build(&job) {
+                Some(build) if build.phase == Phase::Succeeded && !applied => build,
+                _ => continue,
+            };
+            info!(
+                "Updating template {} to image {}",
+                build.template, build.image
+            );
+            // The repository and ref configured by admins are kept
+            let patch: Patch<serde_json::Value> =
+                Patch::Merge(json!({ "spec": { "image": build.image } }));
+            match template_api
+                .patch(&build.template, &PatchParams::default(), &patch)
+                .await
+            {
+                Ok(resource) => {
+                    if let Err(err) = self.record_template_version(&resource).await {
+                        error!(
+                            "Failed to record version of template {}: {}",
+                            build.template, err
+                        );
+                    }
+                }
+                Err(err) => {
+                    error!("Failed to update template {}: {}", build.template, err);
+                    continue;
+                }
+            }
+            let patch: Patch<serde_json::Value> = Patch::Merge(json!({
+                "metadata": { "annotations": { APPLIED_ANNOTATION: now_annotation() } }
+            }));
+            if let Err(err) = job_api
+                .patch(&build.id, &PatchParams::default(), &patch)
+                .await
+            {
+                error!("Failed to mark build {} as applied: {}", build.id, err);
+            }
+        }
+        Ok(())
+    }
 
-        let users = list_users(client, &self.env.namespace).await?;
-        let user = users.get(id);
+    //
+    // The Pod of `session_id`, looked up by `OWNER_LABEL` as claimed warm pods keep their own name.
+    // Falls back to the API so that a pod claimed right before, and not cached yet, is not missed.
+    //
+    async fn session_pod(&self, pod_api: &Api<Pod>, session_id: &str) -> Result<Option<Pod>> {
+        if let Some(pod) = self
+            .pods
+            .list()?
+            .into_iter()
+            .find(|pod| owner_label(pod).as_deref() == Some(session_id))
+        {
+            return Ok(Some(pod));
+        }
+        Ok(
+            list_by_selector(pod_api, format!("{}={}", OWNER_LABEL, session_id))
+                .await?
+                .into_iter()
+                .next(),
+        )
+    }
 
-        match user.map(|user| self.clone().yaml_to_user(&user)) {
-            Some(user) => user.map(Some),
-            None => Ok(None),
+    // Creates the workspace of `user_id` if missing, and marks it as used. Returns the name of its claim.
+    async fn use_workspace(&self, user_id: &str, workspace: &WorkspaceDefaults) -> Result<String> {
+        let claim_api: Api<PersistentVolumeClaim> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        let name = workspace_name(user_id);
+        if get_optional(&claim_api, &name).await?.is_some() {
+            let patch: Patch<serde_json::Value> = Patch::Merge(json!({
+                "metadata": { "annotations": { LAST_USED_ANNOTATION: now_annotation() } }
+            }));
+            claim_api
+                .patch(&name, &PatchParams::default(), &patch)
+                .await
+                .map_err(Error::K8s)?;
+        } else {
+            info!("Creating workspace for user {}", user_id);
+            claim_api
+                .create(
+                    &PostParams::default(),
+                    &create_workspace_claim(user_id, workspace),
+                )
+                .await
+                .map_err(Error::K8s)?;
         }
+        Ok(name)
     }
 
-    pub async fn list_users(&self) -> Result<BTreeMap<String, User>> {
-        let client = new_client().await?;
+    //
+    // Waits for snapshot pod `name` to complete, then decodes the archive it logged.
+    // Logs can be truncated by kubelet rotation, so the archive size is checked against the logged one.
+    //
+    async fn read_snapshot(&self, pod_api: &Api<Pod>, name: &str) -> Result<Vec<u8>> {
+        let started = SystemTime::now();
+        loop {
+            let pod = pod_api.get(name).await.map_err(Error::K8s)?;
+            match pod.status.and_then(|status| status.phase).as_deref() {
+                Some("Succeeded") => {
+                    let logs = pod_api
+                        .logs(name, &LogParams::default())
+                        .await
+                        .map_err(Error::K8s)?;
+                    let mut lines: Vec<&str> = logs.split_whitespace().collect();
+                    let size = lines
+                        .pop()
+                        .and_then(|size| size.parse::<usize>().ok())
+                        .ok_or(Error::MissingData("snapshot size"))?;
+                    let archive =
+                        base64::decode(lines.concat()).map_err(|err| Error::Failure(err.into()))?;
+                    if archive.len() != size {
+                        return Err(Error::Failure(
+                            format!(
+                                "Snapshot {} is truncated: got {} bytes out of {}",
+                                name,
+                                archive.len(),
+                                size
+                            )
+                            .into(),
+                        ));
+                    }
+                    return Ok(archive);
+                }
+                Some("Failed") => {
+                    return Err(Error::Failure(
+                        format!("Snapshot pod {} failed", name).into(),
+                    ))
+                }
+                _ => {
+                    if started.elapsed().unwrap_or_default() > SNAPSHOT_TIMEOUT {
+                        return Err(Error::Failure(
+                            format!("Snapshot pod {} timed out", name).into(),
+                        ));
+                    }
+                    sleep(Duration::from_secs(1)).await
+                }
+            }
+        }
+    }
 
-        Ok(list_users(client, &self.env.namespace)
-            .await?
+    //
+    // Keeps `warm_pool_size` warm pods started per template, on the default pool.
+    // Warm pods started from an outdated version of their template are replaced.
+    //
+    async fn reconcile_warm_pods(&self) -> Result<()> {
+        let pod_api: Api<Pod> = Api::namespaced(self.client.clone(), &self.env.namespace);
+        let warm_pods: Vec<Pod> = self
+            .pods
+            .list()?
             .into_iter()
-            .map(|(k, v)| Ok((k, self.clone().yaml_to_user(&v)?)))
-            .collect::<Result<BTreeMap<String, User>>>()?)
+            .filter(|pod| warm_label(pod).is_some() && pod.metadata.deletion_timestamp.is_none())
+            .collect();
+        // Warm pods of deleted templates are garbage collected along with them
+        for resource in self.templates.list()? {
+            let template_id = resource.name();
+            let template = resource_to_template(&resource).0.and_then(|template| {
+                match session_resources(&self.configuration, &template) {
+                    Ok(resources) => Some((template, resources)),
+                    Err(err) => {
+                        error!("No warm pods for template {}: {}", template_id, err);
+                        None
+                    }
+                }
+            });
+            let hash = template
+                .as_ref()
+                .map(|(template, resources)| create_pod_annotations(template, resources))
+                .transpose()?
+                .and_then(|mut annotations| annotations.remove(SPEC_HASH_ANNOTATION));
+            let (current, outdated): (Vec<&Pod>, Vec<&Pod>) = warm_pods
+                .iter()
+                .filter(|pod| warm_label(pod).as_ref() == Some(&template_id))
+                .partition(|pod| hash.is_some() && pod_spec_hash(pod) == hash.as_ref());
+            // Warm pods are useless when sessions mount workspaces, see `claim_warm_pod`
+            let size = template
+                .as_ref()
+                .filter(|_| self.configuration.workspace.is_none())
+                .and_then(|(template, _)| template.runtime.as_ref())
+                .and_then(|runtime| runtime.warm_pool_size)
+                .unwrap_or(0);
+
+            let deleted: Vec<Pod> = outdated
+                .into_iter()
+                .chain(current.iter().skip(size).copied())
+                .cloned()
+                .collect();
+            let deleted = delete_all(&pod_api, &deleted).await?;
+            if deleted > 0 {
+                info!("Deleted {} warm pods of template {}", deleted, template_id);
+            }
+            if let Some((template, resources)) = template {
+                for _ in current.len()..size {
+                    pod_api
+                        .create(
+                            &PostParams::default(),
+                            &create_warm_pod(
+                                &self.env,
+                                &template_id,
+                                &template,
+                                &resources,
+                                &self.configuration.session.pool_affinity,
+                                owner_reference(&resource)?,
+                            )?,
+                        )
+                        .await
+                        .map_err(Error::K8s)?;
+                }
+            }
+        }
+        Ok(())
     }
 
-    pub async fn create_user(&self, id: String, conf: UserConfiguration) -> Result<()> {
-        let client = new_client().await?;
+    //
+    // Claims a running warm pod started from the same template as `session`, if any.
+    // The pod is relabelled and handed over to `session`, so that it is treated as if created for it.
+    //
+    async fn claim_warm_pod(
+        &self,
+        pod_api: &Api<Pod>,
+        session: &SessionResource,
+        owner: &OwnerReference,
+    ) -> Result<Option<Pod>> {
+        // Warm pods only run on the default pool, and can't mount workspaces nor clone repositories
+        if session.spec.pool_affinity != self.configuration.session.pool_affinity
+            || self.configuration.workspace.is_some()
+            || session.spec.repository.is_some()
+        {
+            return Ok(None);
+        }
+        let hash = create_pod_annotations(&session.spec.template, &session.spec.resources)?
+            .remove(SPEC_HASH_ANNOTATION);
+        let candidates = self.pods.list()?.into_iter().filter(|pod| {
+            warm_label(pod).is_some()
+                && pod.metadata.deletion_timestamp.is_none()
+                && pod_spec_hash(pod) == hash.as_ref()
+                && pod
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.phase.as_deref())
+                    == Some("Running")
+        });
+        for pod in candidates {
+            let claimed_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            // `resourceVersion` makes concurrent claims of the same pod fail
+            let patch: Patch<serde_json::Value> = Patch::Merge(json!({
+                "metadata": {
+                    "resourceVersion": pod.metadata.resource_version,
+                    "labels": { OWNER_LABEL: session.name(), WARM_LABEL: null },
+                    "annotations": { CLAIMED_ANNOTATION: claimed_at.to_string() },
+                    "ownerReferences": [owner],
+                }
+            }));
+            match pod_api
+                .patch(&pod.name(), &PatchParams::default(), &patch)
+                .await
+            {
+                Ok(pod) => return Ok(Some(pod)),
+                Err(kube::Error::Api(err)) if err.code == 409 => continue,
+                Err(err) => return Err(Error::K8s(err)),
+            }
+        }
+        Ok(None)
+    }
 
-        add_config_map_value(
-            client,
-            &self.env.namespace,
-            USERS_CONFIG_MAP,
-            id.as_str(),
-            serde_yaml::to_string(&conf)
-                .map_err(|err| Error::Failure(err.into()))?
-                .as_str(),
-        )
-        .await?;
+    //
+    // Converges the Pod, Service and Ingress of a `Session`, then reports the Pod state in its status.
+    // Pod and Service are owned by the `Session`, the Ingress by the Pod: all are garbage collected on deletion.
+    // Services and Ingresses are looked up in caches, which might lag behind recent creations.
+    //
+    async fn reconcile(&self, client: Client, session: &SessionResource) -> Result<()> {
+        let session_id = session.name();
+        let namespace = &self.env.namespace;
+        let session_api: Api<SessionResource> = Api::namespaced(client.clone(), namespace);
+
+        if session.metadata.deletion_timestamp.is_some() {
+            return Ok(());
+        }
+
+        let owner = owner_reference(session)?;
+        let template = &session.spec.template;
+
+        let pod_api: Api<Pod> = Api::namespaced(client.clone(), namespace);
+        let pod = match self.session_pod(&pod_api, &session_id).await? {
+            Some(pod) => pod,
+            None => match self.claim_warm_pod(&pod_api, session, &owner).await? {
+                Some(pod) => {
+                    info!("Claimed warm pod {} for session {}", pod.name(), session_id);
+                    pod
+                }
+                None => {
+                    info!("Creating pod for session {}", session_id);
+                    let workspace = match &self.configuration.workspace {
+                        Some(workspace) => Some((
+                            self.use_workspace(&session.spec.user_id, workspace).await?,
+                            workspace,
+                        )),
+                        None => None,
+                    };
+                    pod_api
+                        .create(
+                            &PostParams::default(),
+                            &create_pod(
+                                &self.env,
+                                &session_id,
+                                &session.spec,
+                                workspace.as_ref().map(|(claim_name, workspace)| {
+                                    (claim_name.as_str(), *workspace)
+                                }),
+                                owner.clone(),
+                            )?,
+                        )
+                        .await
+                        .map_err(Error::K8s)?
+                }
+            },
+        };
+
+        if self.services.get(&service_name(&session_id))?.is_none() {
+            let service_api: Api<Service> = Api::namespaced(client.clone(), namespace);
+            create_if_missing(&service_api, &create_service(&session_id, template, owner)).await?;
+        }
+
+        if self.ingresses.get(&ingress_name(&session_id))?.is_none() {
+            let ingress_api: Api<Ingress> = Api::namespaced(client, namespace);
+            create_if_missing(
+                &ingress_api,
+                &create_ingress(self, &session_id, template, owner_reference(&pod)?),
+            )
+            .await?;
+        }
+
+        let status = self.clone().pod_to_status(&pod)?;
+
+        // Expose the expiry to the session container, it changes when the session is extended
+        let expires_at = status
+            .start_time
+            .map(|start_time| (start_time + session.spec.duration as i64 * 60).to_string());
+        if expires_at.is_some()
+            && pod
+                .metadata
+                .annotations
+                .as_ref()
+                .and_then(|annotations| annotations.get(EXPIRES_AT_ANNOTATION))
+                != expires_at.as_ref()
+        {
+            let patch: Patch<serde_json::Value> = Patch::Merge(
+                json!({ "metadata": { "annotations": { EXPIRES_AT_ANNOTATION: expires_at } } }),
+            );
+            pod_api
+                .patch(&pod.name(), &PatchParams::default(), &patch)
+                .await
+                .map_err(Error::K8s)?;
+        }
+
+        if session.status.as_ref() != Some(&status) {
+            let patch: Patch<serde_json::Value> = Patch::Merge(json!({ "status": status }));
+            session_api
+                .patch_status(&session_id, &PatchParams::default(), &patch)
+                .await
+                .map_err(Error::K8s)?;
+        }
 
         Ok(())
     }
+}
 
-    pub async fn update_user(&self, id: String, conf: UserUpdateConfiguration) -> Result<()> {
-        let client = new_client().await?;
+#[async_trait]
+impl SessionBackend for Engine {
+    fn env(&self) -> &Environment {
+        &self.env
+    }
 
-        add_config_map_value(
-            client,
-            &self.env.namespace,
-            USERS_CONFIG_MAP,
-            id.as_str(),
-            serde_yaml::to_string(&conf)
-                .map_err(|err| Error::Failure(err.into()))?
-                .as_str(),
-        )
-        .await?;
+    fn configuration(&self) -> &Configuration {
+        &self.configuration
+    }
 
-        Ok(())
+    fn secrets(&self) -> &Secrets {
+        &self.secrets
     }
 
-    pub async fn delete_user(&self, id: String) -> Result<()> {
-        let client = new_client().await?;
-        delete_config_map_value(client, &self.env.namespace, USERS_CONFIG_MAP, id.as_str()).await
+    async fn list_templates(&self) -> Result<BTreeMap<String, Template>> {
+        // Invalid templates are reported in their status by `reconcile_templates`
+        Ok(self
+            .templates
+            .list()?
+            .iter()
+            .filter_map(|resource| {
+                resource_to_template(resource)
+                    .0
+                    .map(|template| (resource.name(), template))
+            })
+            .collect())
     }
 
-    pub async fn get_session(&self, id: &str) -> Result<Option<Session>> {
-        let client = new_client().await?;
-        let pod_api: Api<Pod> = Api::namespaced(client, &self.env.namespace);
-        let pod = pod_api.get(&pod_name(id)).await.ok();
+    async fn update_template(&self, id: &str, template: Template) -> Result<()> {
+        let template_api: Api<TemplateResource> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        let mut resource =
+            TemplateResource::new(id, &template).map_err(|err| Error::Failure(err.into()))?;
+        let resource = match get_optional(&template_api, id).await? {
+            Some(existing) => {
+                // Concurrent updates fail, as the version is checked
+                resource.metadata.resource_version = existing.metadata.resource_version;
+                template_api
+                    .replace(id, &PostParams::default(), &resource)
+                    .await
+                    .map_err(Error::K8s)?
+            }
+            None => template_api
+                .create(&PostParams::default(), &resource)
+                .await
+                .map_err(Error::K8s)?,
+        };
+        // Otherwise left to `reconcile_templates`
+        if let Err(err) = self.record_template_version(&resource).await {
+            error!("Failed to record version of template {}: {}", id, err);
+        }
+        Ok(())
+    }
 
-        match pod.map(|pod| self.clone().pod_to_session(&self.env, &pod)) {
-            Some(session) => session.map(Some),
-            None => Ok(None),
+    async fn delete_template(&self, id: &str) -> Result<()> {
+        // Warm pods and builds are owned by the template, and deleted along with it
+        let template_api: Api<TemplateResource> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        match template_api.delete(id, &DeleteParams::default()).await {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(err)) if err.code == 404 => {
+                Err(Error::TemplateNotFound(id.to_string()))
+            }
+            Err(err) => Err(Error::K8s(err)),
         }
     }
 
-    /// Lists all currently running sessions
-    pub async fn list_sessions(&self) -> Result<BTreeMap<String, Session>> {
-        let client = new_client().await?;
-        let pod_api: Api<Pod> = Api::namespaced(client, &self.env.namespace);
-        let pods = list_by_selector(
-            &pod_api,
-            format!("{}={}", COMPONENT_LABEL, COMPONENT_VALUE).to_string(),
-        )
-        .await?;
+    async fn list_template_versions(&self, id: &str) -> Result<Vec<TemplateVersion>> {
+        let resource = self
+            .templates
+            .get(id)?
+            .ok_or_else(|| Error::TemplateNotFound(id.to_string()))?;
+        let kept = self.kept_template_versions(id)?;
+        // The current version might not be cached yet
+        Ok(match resource_to_template(&resource).0 {
+            Some(template) => record_version(
+                kept,
+                template_version(&resource),
+                &template,
+                self.configuration.template_history_size,
+            ),
+            None => kept,
+        })
+    }
 
-        Ok(pods
-            .iter()
-            .flat_map(|pod| self.clone().pod_to_session(&self.env, pod).ok())
-            .map(|session| (session.clone().user_id, session))
-            .collect::<BTreeMap<String, Session>>())
+    async fn get_user(&self, id: &str) -> Result<Option<User>> {
+        self.users.get(id).await
     }
 
-    pub async fn patch_ingress(&self, templates: &BTreeMap<String, &Template>) -> Result<()> {
-        let client = new_client().await?;
-        let ingress_api: Api<Ingress> = Api::namespaced(client, &self.env.namespace);
-        let mut ingress: Ingress = ingress_api
-            .get(INGRESS_NAME)
-            .await
-            .map_err(|err| Error::Failure(err.into()))?
-            .clone();
-        let mut spec = ingress
-            .clone()
-            .spec
-            .ok_or(Error::MissingData("ingress#spec"))?
-            .clone();
-        let mut rules: Vec<IngressRule> = spec
-            .clone()
-            .rules
-            .ok_or(Error::MissingData("ingreee#spec#rules"))?;
-        for (session_id, template) in templates {
-            let subdomain = subdomain(&self.env.host, &session_id);
-            rules.push(IngressRule {
-                host: Some(subdomain.clone()),
-                http: Some(HTTPIngressRuleValue {
-                    paths: create_ingress_paths(service_name(&session_id), template),
-                }),
-            });
-        }
-        spec.rules.replace(rules);
-        ingress.spec.replace(spec);
+    async fn list_users(&self) -> Result<BTreeMap<String, User>> {
+        self.users.list().await
+    }
 
-        ingress_api
-            .replace(INGRESS_NAME, &PostParams::default(), &ingress)
-            .await
-            .map_err(|err| Error::Failure(err.into()))?;
+    async fn create_user(&self, id: String, conf: UserConfiguration) -> Result<()> {
+        self.users.put(&id, conf.into()).await
+    }
 
-        Ok(())
+    async fn update_user(&self, id: String, conf: UserUpdateConfiguration) -> Result<()> {
+        self.users.put(&id, conf.into()).await
+    }
+
+    async fn delete_user(&self, id: String) -> Result<()> {
+        self.users.delete(&id).await
+    }
+
+    async fn get_session(&self, id: &str) -> Result<Option<Session>> {
+        Ok(self
+            .sessions
+            .get(id)?
+            .map(|resource| self.resource_to_session(&resource)))
     }
 
-    pub async fn create_session(
+    async fn list_sessions(&self) -> Result<BTreeMap<String, Session>> {
+        Ok(self
+            .sessions
+            .list()?
+            .iter()
+            .map(|resource| (resource.name(), self.resource_to_session(resource)))
+            .collect::<BTreeMap<String, Session>>())
+    }
+
+    async fn create_session(
         &self,
         user: &LoggedUser,
-        session_id: &str,
         conf: SessionConfiguration,
-    ) -> Result<()> {
+    ) -> Result<String> {
         // Make sure some node on the right pools still have rooms
         // Find pool affinity, lookup corresponding pool and capacity based on nodes, figure out if there is room left
         // TODO: replace with custom scheduler
         // * https://kubernetes.io/docs/tasks/extend-kubernetes/configure-multiple-schedulers/
         // * https://kubernetes.io/blog/2017/03/advanced-scheduling-in-kubernetes/
-        let pool_id = conf.clone().pool_affinity.unwrap_or_else(|| {
-            user.clone()
-                .pool_affinity
-                .unwrap_or(self.clone().configuration.session.pool_affinity)
-        });
+        let pool_id = pool_affinity(&self.configuration, user, &conf);
+        let _creating = self.creating.lock().await;
         let pool = self
             .get_pool(&pool_id)
             .await?
-            .ok_or(Error::MissingData("no matching pool"))?;
-        let max_sessions_allowed =
-            pool.nodes.len() * self.configuration.session.max_sessions_per_pod;
-        let sessions = self.list_sessions().await?;
-        if sessions.len() >= max_sessions_allowed {
+            .ok_or(Error::NotFound("pool"))?;
+        if pool.available == 0 {
             // TODO Should trigger pool dynamic scalability. Right now this will only consider the pool lower bound.
-            // "Reached maximum number of concurrent sessions allowed: {}"
-            return Err(Error::Unauthorized());
+            return Err(Error::CapacityExhausted(pool_id));
         }
-        let client = new_client().await?;
         // Access the right image id
-        let templates = self.clone().list_templates().await?;
-        let template = templates
-            .get(&conf.template.to_string())
-            .ok_or(Error::MissingData("no matching template"))?;
-
-        let namespace = &self.env.namespace;
-
-        let pod_api: Api<Pod> = Api::namespaced(client.clone(), namespace);
-
-        //TODO deploy a new ingress matching the route
-        // With the proper mapping
-        // Define the correct route
-        // Also deploy proper tcp mapping configmap https://kubernetes.github.io/ingress-nginx/user-guide/exposing-tcp-udp-services/
-
-        let mut sessions = BTreeMap::new();
-        sessions.insert(session_id.to_string(), template);
-        self.patch_ingress(&sessions).await?;
+        let TemplateVersion { version, template } =
+            session_template(self.list_template_versions(&conf.template).await?, &conf)?;
 
+        let resources = session_resources(&self.configuration, &template)?;
+        let repository = session_repository(&template, &conf)?;
         let duration = conf.duration.unwrap_or(self.configuration.session.duration);
 
-        // Deploy a new pod for this image
-        pod_api
-            .create(
-                &PostParams::default(),
-                &create_pod(&self.env, session_id, template, &duration, &pool_id)?,
-            )
-            .await
-            .map_err(|err| Error::Failure(err.into()))?;
-
-        // Deploy the associated service
-        let service_api: Api<Service> = Api::namespaced(client.clone(), namespace);
-        let service = create_service(session_id, template);
-        service_api
-            .create(&PostParams::default(), &service)
+        // Pod, Service and Ingress are then created by the reconcile loop
+        let session_api: Api<SessionResource> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        let session = SessionResource::generate(
+            &session_id_prefix(&user.id),
+            SessionSpec {
+                user_id: user.id.clone(),
+                template_id: conf.template.clone(),
+                template_version: version,
+                template,
+                duration: duration.as_secs() / 60,
+                pool_affinity: pool_id,
+                resources,
+                repository,
+            },
+        );
+        let session = session_api
+            .create(&PostParams::default(), &session)
             .await
-            .map_err(|err| Error::Failure(err.into()))?;
+            .map_err(Error::K8s)?;
+        let name = session.name();
+
+        // Capacity is computed from the cache, the next creation must account for this session
+        let cached = timeout(CACHE_SYNC_TIMEOUT, async {
+            while matches!(self.sessions.get(&name), Ok(None)) {
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await;
+        if cached.is_err() {
+            warn!("Session {} is not cached yet", name);
+        }
 
-        Ok(())
+        Ok(name)
     }
 
-    pub async fn update_session(
+    async fn update_session(
         &self,
         session_id: &str,
         conf: SessionUpdateConfiguration,
     ) -> Result<()> {
         let session = self
             .clone()
-            .get_session(&session_id)
+            .get_session(session_id)
             .await?
-            .ok_or(Error::MissingData("no matching session"))?;
+            .ok_or(Error::NotFound("session"))?;
 
         let duration = conf.duration.unwrap_or(self.configuration.session.duration);
         let max_duration = self.configuration.session.max_duration;
         if duration > max_duration {
-            return Err(Error::Unauthorized());
+            return Err(Error::Validation(format!(
+                "duration exceeds the maximum of {} minutes",
+                max_duration.as_secs() / 60
+            )));
         }
         if duration != session.duration {
-            let client = new_client().await?;
-            let pod_api: Api<Pod> = Api::namespaced(client, &self.env.namespace);
-            let params = PatchParams {
-                ..PatchParams::default()
-            };
-            let patch: Patch<json_patch::Patch> =
-                Patch::Json(json_patch::Patch(vec![PatchOperation::Add(AddOperation {
-                    path: format!(
-                        "/metadata/annotations/{}",
-                        SESSION_DURATION_ANNOTATION.replace("/", "~1")
-                    ),
-                    value: json!(session_duration_annotation(duration)),
-                })]));
-            pod_api
-                .patch(&pod_name(&session.user_id), &params, &patch)
+            let session_api: Api<SessionResource> =
+                Api::namespaced(self.client.clone(), &self.env.namespace);
+            let patch: Patch<serde_json::Value> =
+                Patch::Merge(json!({ "spec": { "duration": duration.as_secs() / 60 } }));
+            session_api
+                .patch(session_id, &PatchParams::default(), &patch)
                 .await
-                .map_err(|err| Error::Failure(err.into()))?;
+                .map_err(Error::K8s)?;
         }
 
         Ok(())
     }
 
-    pub async fn delete_session(&self, id: &str) -> Result<()> {
-        // Undeploy the service by its id
-        let client = new_client().await?;
-        let service_api: Api<Service> = Api::namespaced(client.clone(), &self.env.namespace);
-        service_api
-            .delete(&service_name(id), &DeleteParams::default())
-            .await
-            .map_err(|err| Error::Failure(err.into()))?;
-
-        let pod_api: Api<Pod> = Api::namespaced(client.clone(), &self.env.namespace);
-        pod_api
-            .delete(&pod_name(id), &DeleteParams::default())
+    async fn delete_session(&self, id: &str) -> Result<()> {
+        // Owned resources are cleaned up by the reconcile loop and k8s garbage collection
+        let session_api: Api<SessionResource> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        session_api
+            .delete(id, &DeleteParams::default())
             .await
-            .map_err(|err| Error::Failure(err.into()))?;
-
-        let subdomain = subdomain(&self.env.host, id);
-        let ingress_api: Api<Ingress> = Api::namespaced(client, &self.env.namespace);
-        let mut ingress: Ingress = ingress_api
-            .get(INGRESS_NAME)
-            .await
-            .map_err(|err| Error::Failure(err.into()))?
-            .clone();
-        let mut spec = ingress
-            .clone()
-            .spec
-            .ok_or(Error::MissingData("spec"))?
-            .clone();
-        let rules: Vec<IngressRule> = spec
-            .clone()
-            .rules
-            .unwrap()
-            .into_iter()
-            .filter(|rule| rule.clone().host.unwrap_or_else(|| "unknown".to_string()) != subdomain)
-            .collect();
-        spec.rules.replace(rules);
-        ingress.spec.replace(spec);
-
-        ingress_api
-            .replace(INGRESS_NAME, &PostParams::default(), &ingress)
-            .await
-            .map_err(|err| Error::Failure(err.into()))?;
+            .map_err(Error::K8s)?;
 
         Ok(())
     }
 
-    pub async fn get_pool(&self, id: &str) -> Result<Option<Pool>> {
-        let client = new_client().await?;
-        let node_api: Api<Node> = Api::all(client);
-        let nodes =
-            list_by_selector(&node_api, format!("{}={}", NODE_POOL_LABEL, id).to_string()).await?;
+    fn session_events(&self) -> BoxStream<'static, SessionEvent> {
+        // `Session` statuses are updated by the reconcile loop as soon as their Pod changes
+        let engine = self.clone();
+        receiver_stream(self.sessions.subscribe())
+            .map(move |event| match event {
+                Event::Applied(resource) => SessionEvent::Updated {
+                    id: resource.name(),
+                    session: Box::new(engine.resource_to_session(&resource)),
+                },
+                Event::Deleted(resource) => SessionEvent::Deleted {
+                    id: resource.name(),
+                    user_id: resource.spec.user_id,
+                },
+            })
+            .boxed()
+    }
 
+    async fn get_pool(&self, id: &str) -> Result<Option<Pool>> {
+        let nodes = self
+            .nodes
+            .list()?
+            .into_iter()
+            .filter(|node| {
+                node.metadata
+                    .labels
+                    .as_ref()
+                    .and_then(|labels| labels.get(NODE_POOL_LABEL))
+                    == Some(&id.to_string())
+            })
+            .collect();
+
+        let sessions = self.list_sessions().await?;
         match self.clone().nodes_to_pool(id.to_string(), nodes) {
-            Ok(pool) => Ok(Some(pool)),
+            Ok(pool) => Ok(Some(pool.with_sessions(sessions.values()))),
             Err(_) => Ok(None),
         }
     }
 
-    pub async fn list_pools(&self) -> Result<BTreeMap<String, Pool>> {
-        let client = new_client().await?;
-        let node_api: Api<Node> = Api::all(client);
-
-        let nodes = node_api
-            .list(&ListParams::default())
-            .await
-            .map(|l| l.items)
-            .map_err(|err| Error::Failure(err.into()))?;
+    async fn list_pools(&self) -> Result<BTreeMap<String, Pool>> {
+        let nodes = self.nodes.list()?;
 
         let default = "default".to_string();
         let nodes_by_pool: BTreeMap<String, Vec<Node>> =
@@ -907,12 +2478,268 @@ impl Engine {
                 acc
             });
 
+        let sessions = self.list_sessions().await?;
         Ok(nodes_by_pool
             .into_iter()
             .flat_map(|(s, v)| match self.clone().nodes_to_pool(s.clone(), v) {
-                Ok(pool) => Some((s, pool)),
+                Ok(pool) => Some((s, pool.with_sessions(sessions.values()))),
                 Err(_) => None,
             })
             .collect())
     }
+
+    async fn get_workspace(&self, user_id: &str) -> Result<Option<Workspace>> {
+        let claim_api: Api<PersistentVolumeClaim> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        Ok(get_optional(&claim_api, &workspace_name(user_id))
+            .await?
+            .as_ref()
+            .and_then(claim_to_workspace))
+    }
+
+    async fn list_workspaces(&self) -> Result<BTreeMap<String, Workspace>> {
+        let claim_api: Api<PersistentVolumeClaim> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        let selector = format!(
+            "{}={},{}={}",
+            APP_LABEL, APP_VALUE, COMPONENT_LABEL, WORKSPACE_COMPONENT_VALUE
+        );
+        Ok(list_by_selector(&claim_api, selector)
+            .await?
+            .iter()
+            .filter_map(claim_to_workspace)
+            .map(|workspace| (workspace.user_id.clone(), workspace))
+            .collect())
+    }
+
+    async fn snapshot_workspace(&self, user_id: &str) -> Result<Vec<u8>> {
+        self.get_workspace(user_id)
+            .await?
+            .ok_or(Error::NotFound("workspace"))?;
+        let pod_api: Api<Pod> = Api::namespaced(self.client.clone(), &self.env.namespace);
+        let pod = pod_api
+            .create(&PostParams::default(), &create_snapshot_pod(user_id))
+            .await
+            .map_err(Error::K8s)?;
+        let result = self.read_snapshot(&pod_api, &pod.name()).await;
+        if let Err(err) = pod_api.delete(&pod.name(), &DeleteParams::default()).await {
+            error!("Failed to delete snapshot pod {}: {}", pod.name(), err);
+        }
+        result
+    }
+
+    async fn delete_workspace(&self, user_id: &str) -> Result<()> {
+        // Claims still mounted are only deleted once released
+        let claim_api: Api<PersistentVolumeClaim> =
+            Api::namespaced(self.client.clone(), &self.env.namespace);
+        match claim_api
+            .delete(&workspace_name(user_id), &DeleteParams::default())
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(err)) if err.code == 404 => Err(Error::NotFound("workspace")),
+            Err(err) => Err(Error::K8s(err)),
+        }
+    }
+
+    async fn get_build(&self, id: &str) -> Result<Option<Build>> {
+        Ok(self.builds.get(id)?.as_ref().and_then(job_to_build))
+    }
+
+    async fn list_builds(&self) -> Result<BTreeMap<String, Build>> {
+        Ok(self
+            .builds
+            .list()?
+            .iter()
+            .filter_map(job_to_build)
+            .map(|build| (build.id.clone(), build))
+            .collect())
+    }
+
+    async fn create_build(&self, template_id: &str, repository: Repository) -> Result<String> {
+        let build = self
+            .configuration
+            .build
+            .as_ref()
+            .ok_or_else(|| Error::Validation("image builds are not enabled".to_string()))?;
+        let template = self
+            .templates
+            .get(template_id)?
+            .ok_or_else(|| Error::TemplateNotFound(template_id.to_string()))?;
+        // Also used as image tag
+        let id = format!("{}-build-{}", template_id, now_annotation());
+        let job_api: Api<Job> = Api::namespaced(self.client.clone(), &self.env.namespace);
+        job_api
+            .create(
+                &PostParams::default(),
+                &create_build_job(
+                    &id,
+                    template_id,
+                    &repository,
+                    build,
+                    owner_reference(&template)?,
+                )?,
+            )
+            .await
+            .map_err(Error::K8s)?;
+        Ok(id)
+    }
+
+    async fn collect_garbage(&self) -> Result<CollectedGarbage> {
+        // Fresh lists are required here, caches might lag behind
+        let client = self.client.clone();
+        let namespace = &self.env.namespace;
+        let selector = format!(
+            "{}={},{}={}",
+            APP_LABEL, APP_VALUE, COMPONENT_LABEL, COMPONENT_VALUE
+        );
+
+        // Resources are listed in the reverse order of their creation (Session, Pod, Service then Ingress)
+        // so that a resource created in between is never seen without its dependencies
+        let ingress_api: Api<Ingress> = Api::namespaced(client.clone(), namespace);
+        let ingresses = list_by_selector(&ingress_api, selector.clone()).await?;
+        let service_api: Api<Service> = Api::namespaced(client.clone(), namespace);
+        let services = list_by_selector(&service_api, selector.clone()).await?;
+        let pod_api: Api<Pod> = Api::namespaced(client.clone(), namespace);
+        let pods = list_by_selector(&pod_api, selector).await?;
+        let session_api: Api<SessionResource> = Api::namespaced(client, namespace);
+        let sessions: BTreeSet<String> = session_api
+            .list(&ListParams::default())
+            .await
+            .map_err(Error::K8s)?
+            .into_iter()
+            .filter(|session| session.metadata.deletion_timestamp.is_none())
+            .map(|session| session.name())
+            .collect();
+
+        // Pods are leftovers when their session is gone. Warm pods are not claimed by any session yet.
+        let (pods, orphan_pods): (Vec<Pod>, Vec<Pod>) = pods.into_iter().partition(|pod| {
+            warm_label(pod).is_some()
+                || matches!(owner_label(pod), Some(owner) if sessions.contains(&owner))
+        });
+        let owners: BTreeSet<String> = pods.iter().filter_map(owner_label).collect();
+        // Services and Ingresses are leftovers when their Pod is gone, as they then route to nothing
+        let orphan_services: Vec<Service> = services
+            .into_iter()
+            .filter(
+                |service| !matches!(owner_label(service), Some(owner) if owners.contains(&owner)),
+            )
+            .collect();
+        let orphan_ingresses: Vec<Ingress> = ingresses
+            .into_iter()
+            .filter(
+                |ingress| !matches!(owner_label(ingress), Some(owner) if owners.contains(&owner)),
+            )
+            .collect();
+
+        Ok(CollectedGarbage {
+            pods: delete_all(&pod_api, &orphan_pods).await?,
+            services: delete_all(&service_api, &orphan_services).await?,
+            ingresses: delete_all(&ingress_api, &orphan_ingresses).await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(image: &str) -> Template {
+        Template {
+            name: "name".to_string(),
+            image: image.to_string(),
+            description: "description".to_string(),
+            repository: None,
+            reference: None,
+            tags: None,
+            runtime: None,
+        }
+    }
+
+    fn resource(spec: serde_json::Value) -> TemplateResource {
+        TemplateResource {
+            spec,
+            ..TemplateResource::new("template", &template("image")).unwrap()
+        }
+    }
+
+    fn versions(versions: &[TemplateVersion]) -> Vec<u64> {
+        versions.iter().map(|version| version.version).collect()
+    }
+
+    #[test]
+    fn resources_are_validated() {
+        let (parsed, status) = resource_to_template(&resource(json!({
+            "name": "name", "image": "image", "description": "description"
+        })));
+        assert_eq!(parsed, Some(template("image")));
+        assert!(status.valid);
+        assert!(status.problems.is_empty());
+
+        let (parsed, status) = resource_to_template(&resource(json!({
+            "name": "name", "image": "", "description": "description"
+        })));
+        assert_eq!(parsed, None);
+        assert!(!status.valid);
+        assert_eq!(status.problems, vec!["image is empty"]);
+
+        let (parsed, status) = resource_to_template(&resource(json!({ "name": "name" })));
+        assert_eq!(parsed, None);
+        assert!(!status.valid);
+        assert!(status.problems[0].starts_with("failed to parse"));
+    }
+
+    #[test]
+    fn versions_are_recorded_once() {
+        let recorded = record_version(vec![], 1, &template("image-1"), 3);
+        let recorded = record_version(recorded, 2, &template("image-2"), 3);
+        // Older or already recorded versions are ignored
+        let recorded = record_version(recorded, 2, &template("other"), 3);
+        let recorded = record_version(recorded, 1, &template("other"), 3);
+        assert_eq!(versions(&recorded), vec![1, 2]);
+        assert_eq!(recorded[1].template, template("image-2"));
+
+        // Only the latest `size` versions are kept
+        let recorded = record_version(recorded, 5, &template("image-5"), 3);
+        let recorded = record_version(recorded, 6, &template("image-6"), 3);
+        assert_eq!(versions(&recorded), vec![2, 5, 6]);
+    }
+
+    #[test]
+    fn versions_round_trip_through_config_maps() {
+        let recorded: Vec<TemplateVersion> = vec![2, 10, 11]
+            .into_iter()
+            .map(|version| TemplateVersion {
+                version,
+                template: template(&format!("image-{}", version)),
+            })
+            .collect();
+        let mut config_map =
+            create_versions_config_map("template", &recorded, OwnerReference::default()).unwrap();
+        assert_eq!(config_map.name(), "template-template-versions");
+        // Versions are sorted numerically, not as keys
+        assert_eq!(config_map_to_versions(&config_map), recorded);
+
+        // Invalid entries are skipped
+        let data = config_map.data.as_mut().unwrap();
+        data.insert("invalid".to_string(), "image: image".to_string());
+        data.insert("12".to_string(), "not: a template".to_string());
+        assert_eq!(config_map_to_versions(&config_map), recorded);
+    }
+
+    #[test]
+    fn spec_hashes_identify_specs() {
+        assert_eq!(
+            spec_hash("template", "resources"),
+            spec_hash("template", "resources")
+        );
+        assert_ne!(
+            spec_hash("template", "resources"),
+            spec_hash("template", "other")
+        );
+        assert_ne!(
+            spec_hash("templater", "esources"),
+            spec_hash("template", "resources")
+        );
+    }
 }
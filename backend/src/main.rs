@@ -1,29 +1,35 @@
-#![feature(async_closure, proc_macro_hygiene, decl_macro)]
-
 mod api;
+mod backend;
+mod cache;
+mod crd;
 mod error;
 mod github;
+mod jsonrpc;
 mod kubernetes;
 mod manager;
+mod memory;
 mod metrics;
 mod prometheus;
+mod queue;
 mod types;
+mod users;
 
+use crate::kubernetes::ConfigMapUserStore;
 use crate::manager::Manager;
 use crate::prometheus::PrometheusMetrics;
+use crate::users::FileUserStore;
 use ::prometheus::Registry;
 use github::GitHubUser;
-use rocket::fairing::AdHoc;
-use rocket::{catchers, config::Environment, http::Method, routes};
+use rocket::{catchers, http::Method, routes, Config};
 use rocket_cors::{AllowedOrigins, CorsOptions};
-use rocket_oauth2::{HyperSyncRustlsAdapter, OAuth2, OAuthConfig, StaticProvider};
+use rocket_oauth2::{HyperRustlsAdapter, OAuth2, OAuthConfig, StaticProvider};
 use std::{env, error::Error};
 
 pub struct Context {
     manager: Manager,
 }
 
-#[tokio::main]
+#[rocket::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     // Initialize log configuration. Reads `RUST_LOG` if any, otherwise fallsback to `default`
     if env::var("RUST_LOG").is_err() {
@@ -31,25 +37,72 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }
     env_logger::init();
 
+    // Maintenance commands, executed instead of the server
+    let command = env::args().nth(1);
+    match command.as_deref() {
+        // Imports users from the `playground-users` ConfigMap into a local file
+        Some("migrate-users") => {
+            let path = env::args()
+                .nth(2)
+                .ok_or("Usage: playground migrate-users <path>")?;
+            let count = users::migrate(
+                &ConfigMapUserStore::from_context().await?,
+                &FileUserStore::open(&path)?,
+            )
+            .await?;
+            log::info!("Imported {} users into {}", count, path);
+            return Ok(());
+        }
+        // Imports templates from the `playground-templates` ConfigMap as `Template` resources
+        Some("migrate-templates") => {
+            let count = kubernetes::import_templates_config_map().await?;
+            log::info!("Imported {} templates", count);
+            return Ok(());
+        }
+        // Removes rules of the shared ingress left over by sessions
+        Some("prune-ingress-rules") => {
+            let count = kubernetes::prune_ingress_rules().await?;
+            log::info!("Pruned {} ingress rules", count);
+            return Ok(());
+        }
+        Some("print-crds") => {
+            for crd in crd::crds() {
+                print!("{}", serde_yaml::to_string(&crd)?);
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
     // Prints basic details
-    log::info!("Running ROCKET in {:?} mode", Environment::active()?);
+    log::info!(
+        "Running ROCKET with {} profile",
+        Config::figment().profile()
+    );
 
     match env::var("GITHUB_SHA") {
         Ok(version) => log::info!("Version {}", version),
         Err(_) => log::warn!("Unknown version"),
     }
 
-    let manager = Manager::new().await?;
-    let engine = manager.clone().engine;
-    manager.clone().spawn_background_thread();
+    let engine = backend::new().await?;
+    let manager = Manager::new(engine.clone())?;
+    manager.clone().spawn_background_task();
+    manager.clone().spawn_admission_task();
 
     // Configure CORS
     let cors = CorsOptions {
         allowed_origins: AllowedOrigins::all(),
-        allowed_methods: vec![Method::Get, Method::Post, Method::Put, Method::Delete]
-            .into_iter()
-            .map(From::from)
-            .collect(),
+        allowed_methods: vec![
+            Method::Get,
+            Method::Post,
+            Method::Put,
+            Method::Patch,
+            Method::Delete,
+        ]
+        .into_iter()
+        .map(From::from)
+        .collect(),
         allow_credentials: true,
         ..Default::default()
     }
@@ -58,53 +111,74 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let registry = Registry::new_custom(Some("playground".to_string()), None)?;
     manager.clone().metrics.register(registry.clone())?;
     let prometheus = PrometheusMetrics::with_registry(registry);
-    let error = rocket::ignite()
-        .register(catchers![api::bad_request_catcher])
+    let oauth_config = OAuthConfig::new(
+        StaticProvider {
+            auth_uri: "https://github.com/login/oauth/authorize".into(),
+            token_uri: "https://github.com/login/oauth/access_token".into(),
+        },
+        engine.configuration().github_client_id.clone(),
+        engine.secrets().github_client_secret.clone(),
+        None,
+    );
+    rocket::build()
+        .register("/", catchers![api::default_catcher])
         .attach(cors)
-        .attach(AdHoc::on_attach("github", |rocket| {
-            let config = OAuthConfig::new(
-                StaticProvider {
-                    auth_uri: "https://github.com/login/oauth/authorize".into(),
-                    token_uri: "https://github.com/login/oauth/access_token".into(),
-                },
-                engine.configuration.github_client_id,
-                engine.secrets.github_client_secret,
-                None,
-            );
-            Ok(rocket.attach(OAuth2::<GitHubUser>::custom(
-                HyperSyncRustlsAdapter::default().basic_auth(false),
-                config,
-            )))
-        }))
+        .attach(OAuth2::<GitHubUser>::custom(
+            HyperRustlsAdapter::default().basic_auth(false),
+            oauth_config,
+        ))
         .mount(
             "/api",
             routes![
                 api::get,
                 api::get_unlogged,
+                // Templates
+                api::update_template,
+                api::patch_template,
+                api::delete_template,
+                api::list_template_versions,
+                api::rollback_template,
                 // Users
                 api::get_user,
                 api::list_users,
                 api::create_user,
                 api::update_user,
                 api::delete_user,
-                // Current Session
-                api::get_current_session,
-                api::get_current_session_unlogged,
+                // Sessions of the current user
+                api::list_current_sessions,
+                api::list_current_sessions_unlogged,
+                api::watch_current_sessions,
+                api::watch_current_sessions_unlogged,
                 api::create_current_session,
                 api::create_current_session_unlogged,
-                api::update_current_session,
-                api::update_current_session_unlogged,
-                api::delete_current_session,
-                api::delete_current_session_unlogged,
+                // Queued sessions of the current user
+                api::list_queued_sessions,
+                api::list_queued_sessions_unlogged,
+                api::watch_queued_sessions,
+                api::watch_queued_sessions_unlogged,
+                api::cancel_queued_session,
+                api::cancel_queued_session_unlogged,
                 // Sessions
                 api::get_session,
                 api::list_sessions,
-                api::create_session,
+                api::watch_sessions,
                 api::update_session,
+                api::heartbeat_session,
                 api::delete_session,
                 // Pools
                 api::get_pool,
                 api::list_pools,
+                // Workspaces
+                api::get_workspace,
+                api::list_workspaces,
+                api::snapshot_workspace,
+                api::delete_workspace,
+                // Builds
+                api::get_build,
+                api::list_builds,
+                api::create_build,
+                // JSON-RPC
+                api::rpc,
                 // Login
                 api::github_login,
                 api::post_install_callback,
@@ -114,8 +188,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
         )
         .mount("/metrics", prometheus)
         .manage(Context { manager })
-        .launch();
+        .launch()
+        .await?;
 
-    // Launch blocks unless an error is returned
-    Err(error.into())
+    Ok(())
 }
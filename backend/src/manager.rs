@@ -1,21 +1,33 @@
 use crate::{
+    backend::{
+        build_repository, pool_affinity, receiver_stream, Configuration, Environment,
+        SessionBackend,
+    },
     error::{Error, Result},
-    kubernetes::{Configuration, Engine, Environment},
     metrics::Metrics,
+    queue::AdmissionQueue,
     types::{
-        LoggedUser, Phase, Pool, Session, SessionConfiguration, SessionUpdateConfiguration,
-        Template, User, UserConfiguration, UserUpdateConfiguration,
+        Admission, Build, BuildConfiguration, LoggedUser, Phase, Pool, QueuedSession, Quota,
+        Session, SessionConfiguration, SessionEvent, SessionUpdateConfiguration, Template,
+        TemplateVersion, User, UserConfiguration, UserUpdateConfiguration, Workspace,
     },
 };
+use futures::{
+    future,
+    stream::{self, BoxStream, StreamExt},
+};
 use log::{error, info, warn};
 use serde::Serialize;
 use std::{
     collections::{BTreeMap, HashSet},
     sync::{Arc, Mutex},
-    thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{broadcast, Mutex as AsyncMutex},
+    task::JoinHandle,
+    time::sleep,
 };
-use tokio::runtime::Runtime;
 
 fn running_sessions(sessions: Vec<&Session>) -> Vec<&Session> {
     sessions
@@ -26,9 +38,60 @@ fn running_sessions(sessions: Vec<&Session>) -> Vec<&Session> {
 
 #[derive(Clone)]
 pub struct Manager {
-    pub engine: Engine,
+    pub engine: Arc<dyn SessionBackend>,
     pub metrics: Metrics,
     sessions: Arc<Mutex<HashSet<String>>>,
+    // Session minutes booked today, by user id
+    booked_minutes: Arc<Mutex<BTreeMap<String, BookedMinutes>>>,
+    queue: AdmissionQueue,
+    // Last reported activity of sessions, by session id
+    activity: Arc<Mutex<BTreeMap<String, SystemTime>>>,
+    // Held while admitting sessions, so that each queued one is admitted once and quotas are not exceeded
+    admitting: Arc<AsyncMutex<()>>,
+    // Expiry warnings, merged into session events
+    warnings: broadcast::Sender<SessionEvent>,
+    // Ids of sessions already warned of their expiry
+    warned: Arc<Mutex<HashSet<String>>>,
+}
+
+struct BookedMinutes {
+    // Days since UNIX epoch
+    day: u64,
+    minutes: u64,
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / (24 * 60 * 60)
+}
+
+// Template ids are used as Kubernetes resource names and label values
+fn validate_template_id(id: &str) -> Result<()> {
+    let valid = !id.is_empty()
+        && id.len() <= 63
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !id.starts_with('-')
+        && !id.ends_with('-');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Validation(format!("invalid template id '{}'", id)))
+    }
+}
+
+// `template`, if it has no problem
+fn validated(template: Template) -> Result<Template> {
+    let problems = template.validate();
+    if problems.is_empty() {
+        Ok(template)
+    } else {
+        Err(Error::Validation(problems.join(", ")))
+    }
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -41,249 +104,799 @@ pub struct Playground {
 
 impl Manager {
     const SLEEP_TIME: Duration = Duration::from_secs(60);
+    const WARNINGS_CAPACITY: usize = 16;
 
-    pub async fn new() -> Result<Self> {
+    pub fn new(engine: Arc<dyn SessionBackend>) -> Result<Self> {
         let metrics = Metrics::new().map_err(|err| Error::Failure(err.into()))?;
-        let engine = Engine::new().await?;
-        // Go through all existing sessions and update the ingress
-        match engine.clone().list_sessions().await {
-            Ok(sessions) => {
-                let running = running_sessions(sessions.values().collect())
-                    .iter()
-                    .map(|i| (i.user_id.clone(), &i.template))
-                    .collect();
-                engine.clone().patch_ingress(&running).await?;
-
-                if running.is_empty() {
-                    info!("No sesssions restored");
-                } else {
-                    info!("Restored sesssions for {:?}", running.keys());
-                }
-            }
-            Err(err) => error!(
-                "Failed to call list_all: {}. Existing sessions won't be accessible",
-                err
-            ),
-        }
+        let (warnings, _) = broadcast::channel(Manager::WARNINGS_CAPACITY);
         Ok(Manager {
             engine,
             metrics,
             sessions: Arc::new(Mutex::new(HashSet::new())), // Temp map used to track session deployment time
+            booked_minutes: Arc::new(Mutex::new(BTreeMap::new())),
+            queue: AdmissionQueue::new(),
+            activity: Arc::new(Mutex::new(BTreeMap::new())),
+            admitting: Arc::new(AsyncMutex::new(())),
+            warnings,
+            warned: Arc::new(Mutex::new(HashSet::new())),
         })
     }
 
-    pub fn spawn_background_thread(self) -> JoinHandle<()> {
-        thread::spawn(move || loop {
-            thread::sleep(Manager::SLEEP_TIME);
-
-            // Track some deployments metrics
-            if let Ok(runtime) = new_runtime() {
-                let sessions_thread = self.clone().sessions.clone();
-                if let Ok(mut sessions2) = sessions_thread.lock() {
-                    let sessions3 = &mut sessions2.clone();
-                    for id in sessions3.iter() {
-                        match runtime.block_on(self.engine.get_session(&session_id(id))) {
-                            Ok(Some(session)) => {
-                                // Deployed sessions are removed from the set
-                                // Additionally the deployment time is tracked
-                                match session.pod.phase {
-                                    Phase::Running | Phase::Failed => {
-                                        sessions2.remove(&session.user_id);
-                                        if let Some(duration) =
-                                            &session.pod.start_time.and_then(|p| p.elapsed().ok())
-                                        {
-                                            self.clone()
-                                                .metrics
-                                                .observe_deploy_duration(duration.as_secs_f64());
-                                        } else {
-                                            error!("Failed to compute this session lifetime");
-                                        }
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            Err(err) => {
-                                warn!("Failed to call get: {}", err);
-                                sessions2.remove(id);
-                            }
-                            Ok(None) => warn!("No matching pod: {}", id),
-                        }
-                    }
-                } else {
-                    error!("Failed to acquire sessions lock");
+    ///
+    /// Spawns a task periodically tracking deployments and undeploying expired sessions.
+    ///
+    pub fn spawn_background_task(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                sleep(Manager::SLEEP_TIME).await;
+
+                self.track_deployments().await;
+                self.undeploy_expired_sessions().await;
+                self.warn_expiring_sessions().await;
+                self.undeploy_idle_sessions().await;
+                self.admit_queued_sessions().await;
+                self.collect_expired_workspaces().await;
+                self.collect_garbage().await;
+            }
+        })
+    }
+
+    ///
+    /// Spawns a task admitting queued sessions as soon as sessions are deleted.
+    ///
+    pub fn spawn_admission_task(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut events = self.engine.session_events();
+            while let Some(event) = events.next().await {
+                if let SessionEvent::Deleted { .. } = event {
+                    self.admit_queued_sessions().await;
                 }
+            }
+        })
+    }
 
-                // Go through all Running pods and figure out if they have to be undeployed
-                match runtime.block_on(self.engine.list_sessions()) {
-                    Ok(sessions) => {
-                        for session in running_sessions(sessions.values().collect()) {
+    // Track some deployments metrics
+    async fn track_deployments(&self) {
+        let ids = match self.sessions.lock() {
+            Ok(sessions) => sessions.clone(),
+            Err(_) => {
+                error!("Failed to acquire sessions lock");
+                return;
+            }
+        };
+        for id in ids {
+            let deployed = match self.engine.get_session(&id).await {
+                Ok(Some(session)) => {
+                    // Deployed sessions are removed from the set
+                    // Additionally the deployment time is tracked
+                    match session.pod.phase {
+                        Phase::Running | Phase::Failed => {
                             if let Some(duration) =
                                 &session.pod.start_time.and_then(|p| p.elapsed().ok())
                             {
-                                if duration > &session.duration {
-                                    info!("Undeploying {}", session.user_id);
-
-                                    match runtime.block_on(
-                                        self.engine.delete_session(&session_id(&session.user_id)),
-                                    ) {
-                                        Ok(()) => (),
-                                        Err(err) => {
-                                            warn!(
-                                                "Error while undeploying {}: {}",
-                                                session.user_id, err
-                                            )
-                                        }
-                                    }
-                                }
+                                self.metrics.observe_deploy_duration(duration.as_secs_f64());
                             } else {
                                 error!("Failed to compute this session lifetime");
                             }
+                            true
                         }
+                        _ => false,
                     }
-                    Err(err) => error!("Failed to call list_all: {}", err),
+                }
+                Err(err) => {
+                    warn!("Failed to call get: {}", err);
+                    true
+                }
+                Ok(None) => {
+                    warn!("No matching pod: {}", id);
+                    false
+                }
+            };
+            if deployed {
+                if let Ok(mut sessions) = self.sessions.lock() {
+                    sessions.remove(&id);
+                } else {
+                    error!("Failed to acquire sessions lock");
                 }
             }
-        })
+        }
     }
-}
 
-fn new_runtime() -> Result<Runtime> {
-    Runtime::new().map_err(|err| Error::Failure(err.into()))
-}
+    // Go through all Running pods and figure out if they have to be undeployed
+    async fn undeploy_expired_sessions(&self) {
+        match self.engine.list_sessions().await {
+            Ok(sessions) => {
+                for session in running_sessions(sessions.values().collect()) {
+                    if let Some(duration) = &session.pod.start_time.and_then(|p| p.elapsed().ok()) {
+                        if duration > &session.duration {
+                            info!("Undeploying {}", session.id);
+
+                            if let Err(err) = self.engine.delete_session(&session.id).await {
+                                warn!("Error while undeploying {}: {}", session.id, err)
+                            }
+                        }
+                    } else {
+                        error!("Failed to compute this session lifetime");
+                    }
+                }
+            }
+            Err(err) => error!("Failed to call list_all: {}", err),
+        }
+    }
+
+    // Emit a `SessionEvent::Expiring` once for each session about to expire
+    async fn warn_expiring_sessions(&self) {
+        let sessions = match self.engine.list_sessions().await {
+            Ok(sessions) => sessions,
+            Err(err) => {
+                error!("Failed to call list_all: {}", err);
+                return;
+            }
+        };
+        let expiry_warning = self.engine.configuration().session.expiry_warning;
+        let mut warned = match self.warned.lock() {
+            Ok(warned) => warned,
+            Err(_) => {
+                error!("Failed to acquire warned lock");
+                return;
+            }
+        };
+        // Forget deleted sessions, and extended ones so that they are warned again
+        let expiring = |session: &Session| match session.remaining {
+            Some(remaining) if remaining <= expiry_warning => Some(remaining),
+            _ => None,
+        };
+        warned.retain(|id| sessions.get(id).and_then(expiring).is_some());
+        for session in running_sessions(sessions.values().collect()) {
+            if let Some(remaining) = expiring(session) {
+                if warned.insert(session.id.clone()) {
+                    // Fails only when there is no receiver
+                    let _ = self.warnings.send(SessionEvent::Expiring {
+                        id: session.id.clone(),
+                        user_id: session.user_id.clone(),
+                        remaining,
+                    });
+                }
+            }
+        }
+    }
+
+    // Engine session events, along with expiry warnings
+    fn session_events(&self) -> BoxStream<'static, SessionEvent> {
+        stream::select(
+            self.engine.session_events(),
+            receiver_stream(self.warnings.subscribe()),
+        )
+        .boxed()
+    }
+
+    // Undeploy running sessions with no reported activity for longer than their idle timeout
+    async fn undeploy_idle_sessions(&self) {
+        let sessions = match self.engine.list_sessions().await {
+            Ok(sessions) => sessions,
+            Err(err) => {
+                error!("Failed to call list_all: {}", err);
+                return;
+            }
+        };
+        let now = SystemTime::now();
+        let idle_sessions: Vec<&Session> = match self.activity.lock() {
+            Ok(mut activity) => {
+                // Forget deleted sessions
+                activity.retain(|id, _| sessions.contains_key(id));
+                running_sessions(sessions.values().collect())
+                    .into_iter()
+                    .filter(|session| {
+                        let timeout = match self.idle_timeout(session) {
+                            Some(timeout) => timeout,
+                            None => return false,
+                        };
+                        // Sessions with no reported activity yet, e.g. after a restart, start idling now
+                        let last_activity = *activity.entry(session.id.clone()).or_insert(now);
+                        matches!(now.duration_since(last_activity), Ok(idle) if idle > timeout)
+                    })
+                    .collect()
+            }
+            Err(_) => {
+                error!("Failed to acquire activity lock");
+                return;
+            }
+        };
+        for session in idle_sessions {
+            info!("Undeploying idle {}", session.id);
+
+            if let Err(err) = self.engine.delete_session(&session.id).await {
+                warn!("Error while undeploying {}: {}", session.id, err)
+            }
+        }
+    }
+
+    // Idle timeout of `session`, if any. Set by its template, or defaults to the configured one.
+    fn idle_timeout(&self, session: &Session) -> Option<Duration> {
+        session
+            .template
+            .runtime
+            .as_ref()
+            .and_then(|runtime| runtime.idle_timeout)
+            .map(|minutes| Duration::from_secs(minutes * 60))
+            .or(self.engine.configuration().session.idle_timeout)
+    }
 
-fn session_id(id: &str) -> String {
-    // Create a unique ID for this session. Use lowercase to make sure the result can be used as part of a DNS
-    id.to_string().to_lowercase()
+    // Create queued sessions, in order, as long as their pool has capacity
+    async fn admit_queued_sessions(&self) {
+        let _admitting = self.admitting.lock().await;
+        let entries = match self.queue.entries() {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!("Failed to list queued sessions: {}", err);
+                return;
+            }
+        };
+        // Pools with no capacity left. Later entries for those keep waiting.
+        let mut exhausted_pools = HashSet::new();
+        for entry in entries {
+            if exhausted_pools.contains(&entry.pool_affinity) {
+                continue;
+            }
+            match self.create_admitted_session(&entry.user, entry.conf).await {
+                Ok(session_id) => info!("Admitted {} as session {}", entry.id, session_id),
+                Err(Error::CapacityExhausted(_)) => {
+                    exhausted_pools.insert(entry.pool_affinity);
+                    continue;
+                }
+                Err(err) => warn!("Dropping {}: {}", entry.id, err),
+            }
+            if let Err(err) = self.queue.remove(&entry.id) {
+                error!("Failed to dequeue {}: {}", entry.id, err);
+            }
+        }
+    }
+
+    // Delete workspaces unused for longer than the configured retention period
+    async fn collect_expired_workspaces(&self) {
+        let retention = match self
+            .engine
+            .configuration()
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.retention_days)
+        {
+            Some(days) => Duration::from_secs(days * 24 * 60 * 60),
+            None => return,
+        };
+        let (workspaces, sessions) = match future::try_join(
+            self.engine.list_workspaces(),
+            self.engine.list_sessions(),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(err) => {
+                error!("Failed to list workspaces: {}", err);
+                return;
+            }
+        };
+        for (user_id, workspace) in workspaces {
+            let in_use = sessions.values().any(|session| session.user_id == user_id);
+            let expired = matches!(workspace.last_used.and_then(|last_used| last_used.elapsed().ok()), Some(unused) if unused > retention);
+            if expired && !in_use {
+                info!("Deleting expired workspace of {}", user_id);
+                if let Err(err) = self.engine.delete_workspace(&user_id).await {
+                    warn!("Error while deleting workspace of {}: {}", user_id, err);
+                }
+            }
+        }
+    }
+
+    // Clean up resources left behind by failed session creations or deletions
+    async fn collect_garbage(&self) {
+        match self.engine.collect_garbage().await {
+            Ok(garbage) => {
+                if !garbage.is_empty() {
+                    info!("Collected garbage: {:?}", garbage);
+                }
+                self.metrics
+                    .inc_garbage_collected_counter("pod", garbage.pods);
+                self.metrics
+                    .inc_garbage_collected_counter("service", garbage.services);
+                self.metrics
+                    .inc_garbage_collected_counter("ingress", garbage.ingresses);
+            }
+            Err(err) => error!("Failed to collect garbage: {}", err),
+        }
+    }
 }
 
 impl Manager {
-    pub fn get(self, user: LoggedUser) -> Result<Playground> {
-        let templates = new_runtime()?.block_on(self.clone().engine.list_templates())?;
+    pub async fn get(&self, user: LoggedUser) -> Result<Playground> {
+        let templates = self.list_templates(Some(&user)).await?;
         Ok(Playground {
             templates,
             user: Some(user),
-            env: self.engine.env,
-            configuration: self.engine.configuration,
+            env: self.engine.env().clone(),
+            configuration: self.engine.configuration().clone(),
         })
     }
 
-    pub fn get_unlogged(&self) -> Result<Playground> {
-        let templates = new_runtime()?.block_on(self.clone().engine.list_templates())?;
+    pub async fn get_unlogged(&self) -> Result<Playground> {
+        let templates = self.list_templates(None).await?;
         Ok(Playground {
             templates,
             user: None,
-            env: self.clone().engine.env,
-            configuration: self.clone().engine.configuration,
+            env: self.engine.env().clone(),
+            configuration: self.engine.configuration().clone(),
         })
     }
 
+    // Templates
+
+    /// Templates visible to `user`, see `Template::is_visible_to`
+    pub async fn list_templates(
+        &self,
+        user: Option<&LoggedUser>,
+    ) -> Result<BTreeMap<String, Template>> {
+        Ok(self
+            .engine
+            .list_templates()
+            .await?
+            .into_iter()
+            .filter(|(_, template)| template.is_visible_to(user))
+            .collect())
+    }
+
+    ///
+    /// Creates template `id` from `content`, a YAML or JSON `Template`, or replaces it.
+    ///
+    /// Running sessions are not affected, they keep the template they have been created from.
+    ///
+    pub async fn update_template(&self, user: &LoggedUser, id: &str, content: &str) -> Result<()> {
+        if !user.has_admin_edit_rights() {
+            return Err(Error::Forbidden("admin edit rights required"));
+        }
+
+        validate_template_id(id)?;
+        let template =
+            serde_yaml::from_str(content).map_err(|err| Error::Validation(err.to_string()))?;
+        self.engine.update_template(id, validated(template)?).await
+    }
+
+    ///
+    /// Updates template `id` with `content`, a YAML or JSON merge patch (RFC 7386) of its `Template`.
+    ///
+    pub async fn patch_template(&self, user: &LoggedUser, id: &str, content: &str) -> Result<()> {
+        if !user.has_admin_edit_rights() {
+            return Err(Error::Forbidden("admin edit rights required"));
+        }
+
+        let template = self
+            .engine
+            .list_templates()
+            .await?
+            .remove(id)
+            .ok_or_else(|| Error::TemplateNotFound(id.to_string()))?;
+        let mut value = serde_json::to_value(template).map_err(|err| Error::Failure(err.into()))?;
+        let patch =
+            serde_yaml::from_str(content).map_err(|err| Error::Validation(err.to_string()))?;
+        json_patch::merge(&mut value, &patch);
+        let template =
+            serde_json::from_value(value).map_err(|err| Error::Validation(err.to_string()))?;
+        self.engine.update_template(id, validated(template)?).await
+    }
+
+    /// Versions kept of template `id`, oldest first
+    pub async fn list_template_versions(
+        &self,
+        user: &LoggedUser,
+        id: &str,
+    ) -> Result<Vec<TemplateVersion>> {
+        if !user.has_admin_read_rights() {
+            return Err(Error::Forbidden("admin read rights required"));
+        }
+
+        self.engine.list_template_versions(id).await
+    }
+
+    ///
+    /// Rolls template `id` back to `version`, which is kept as a new version.
+    ///
+    pub async fn rollback_template(&self, user: &LoggedUser, id: &str, version: u64) -> Result<()> {
+        if !user.has_admin_edit_rights() {
+            return Err(Error::Forbidden("admin edit rights required"));
+        }
+
+        let template = self
+            .engine
+            .list_template_versions(id)
+            .await?
+            .into_iter()
+            .find(|candidate| candidate.version == version)
+            .ok_or(Error::NotFound("template version"))?
+            .template;
+        self.engine.update_template(id, template).await
+    }
+
+    ///
+    /// Deletes template `id`. Fails if some sessions were created from it, unless `force`d.
+    ///
+    /// Sessions are not deleted, they keep running until they expire.
+    ///
+    pub async fn delete_template(&self, user: &LoggedUser, id: &str, force: bool) -> Result<()> {
+        if !user.has_admin_edit_rights() {
+            return Err(Error::Forbidden("admin edit rights required"));
+        }
+
+        if !force {
+            let count = self
+                .engine
+                .list_sessions()
+                .await?
+                .values()
+                .filter(|session| session.template_id == id)
+                .count();
+            if count > 0 {
+                return Err(Error::Conflict(format!(
+                    "template is used by {} sessions",
+                    count
+                )));
+            }
+        }
+        self.engine.delete_template(id).await
+    }
+
     // Users
 
-    pub fn get_user(&self, user: &LoggedUser, id: &str) -> Result<Option<User>> {
+    pub async fn get_user(&self, user: &LoggedUser, id: &str) -> Result<Option<User>> {
         if user.has_admin_read_rights() {
-            new_runtime()?.block_on(self.engine.get_user(&id))
+            self.engine.get_user(id).await
         } else {
-            Err(Error::Unauthorized())
+            Err(Error::Forbidden("admin read rights required"))
         }
     }
 
-    pub fn list_users(&self, user: &LoggedUser) -> Result<BTreeMap<String, User>> {
+    pub async fn list_users(&self, user: &LoggedUser) -> Result<BTreeMap<String, User>> {
         if user.has_admin_read_rights() {
-            new_runtime()?.block_on(self.engine.list_users())
+            self.engine.list_users().await
         } else {
-            Err(Error::Unauthorized())
+            Err(Error::Forbidden("admin read rights required"))
         }
     }
 
-    pub fn create_user(self, user: &LoggedUser, id: String, conf: UserConfiguration) -> Result<()> {
+    pub async fn create_user(
+        &self,
+        user: &LoggedUser,
+        id: String,
+        conf: UserConfiguration,
+    ) -> Result<()> {
         if user.has_admin_edit_rights() {
-            new_runtime()?.block_on(self.engine.create_user(id, conf))
+            self.engine.create_user(id, conf).await
         } else {
-            Err(Error::Unauthorized())
+            Err(Error::Forbidden("admin edit rights required"))
         }
     }
 
-    pub fn update_user(
-        self,
+    pub async fn update_user(
+        &self,
         user: LoggedUser,
         id: String,
         conf: UserUpdateConfiguration,
     ) -> Result<()> {
         if user.has_admin_edit_rights() {
-            new_runtime()?.block_on(self.engine.update_user(id, conf))
+            self.engine.update_user(id, conf).await
         } else {
-            Err(Error::Unauthorized())
+            Err(Error::Forbidden("admin edit rights required"))
         }
     }
 
-    pub fn delete_user(self, user: &LoggedUser, id: String) -> Result<()> {
+    pub async fn delete_user(&self, user: &LoggedUser, id: String) -> Result<()> {
         if user.has_admin_edit_rights() {
-            new_runtime()?.block_on(self.engine.delete_user(id))
+            self.engine.delete_user(id).await
         } else {
-            Err(Error::Unauthorized())
+            Err(Error::Forbidden("admin edit rights required"))
         }
     }
 
     // Sessions
 
-    pub fn get_session(&self, user: &LoggedUser, id: &str) -> Result<Option<Session>> {
-        if !user.has_admin_read_rights() {
-            return Err(Error::Unauthorized());
+    // Returns session `id` if it is owned by `user`, or if `admin` allows `user` to access others sessions.
+    // Sessions of others are reported as missing, unless `user` could at least read them.
+    async fn accessible_session(
+        &self,
+        user: &LoggedUser,
+        id: &str,
+        admin: bool,
+    ) -> Result<Option<Session>> {
+        match self.engine.get_session(id).await? {
+            Some(session) if session.user_id == user.id || admin => Ok(Some(session)),
+            Some(_) if user.has_admin_read_rights() => {
+                Err(Error::Forbidden("admin edit rights required"))
+            }
+            _ => Ok(None),
         }
+    }
 
-        new_runtime()?.block_on(self.engine.get_session(&session_id(id)))
+    pub async fn get_session(&self, user: &LoggedUser, id: &str) -> Result<Option<Session>> {
+        self.accessible_session(user, id, user.has_admin_read_rights())
+            .await
     }
 
-    pub fn list_sessions(&self, user: &LoggedUser) -> Result<BTreeMap<String, Session>> {
+    pub async fn list_sessions(&self, user: &LoggedUser) -> Result<BTreeMap<String, Session>> {
         if user.has_admin_read_rights() {
-            new_runtime()?.block_on(self.engine.list_sessions())
+            self.engine.list_sessions().await
         } else {
-            Err(Error::Unauthorized())
+            Err(Error::Forbidden("admin read rights required"))
         }
     }
 
-    pub fn create_session(
+    /// Lists sessions owned by `user`
+    pub async fn list_user_sessions(&self, user: &LoggedUser) -> Result<Vec<Session>> {
+        Ok(self
+            .engine
+            .list_sessions()
+            .await?
+            .values()
+            .filter(|session| session.user_id == user.id)
+            .cloned()
+            .collect())
+    }
+
+    ///
+    /// Streams changes of sessions owned by `user`, starting with their current state.
+    ///
+    pub async fn watch_user_sessions(
         &self,
         user: &LoggedUser,
-        id: &str,
-        conf: SessionConfiguration,
+    ) -> Result<BoxStream<'static, SessionEvent>> {
+        let user_id = user.id.clone();
+        // Subscribe first so that no change is missed in between
+        let events = self.session_events();
+        let current = self
+            .list_user_sessions(user)
+            .await?
+            .into_iter()
+            .map(|session| SessionEvent::Updated {
+                id: session.id.clone(),
+                session: Box::new(session),
+            });
+        Ok(stream::iter(current)
+            .chain(events.filter(move |event| future::ready(event.user_id() == user_id)))
+            .boxed())
+    }
+
+    ///
+    /// Streams changes of all sessions, starting with their current state.
+    ///
+    pub async fn watch_sessions(
+        &self,
+        user: &LoggedUser,
+    ) -> Result<BoxStream<'static, SessionEvent>> {
+        if !user.has_admin_read_rights() {
+            return Err(Error::Forbidden("admin read rights required"));
+        }
+
+        let events = self.session_events();
+        let current = self
+            .engine
+            .list_sessions()
+            .await?
+            .into_iter()
+            .map(|(id, session)| SessionEvent::Updated {
+                id,
+                session: Box::new(session),
+            });
+        Ok(stream::iter(current).chain(events).boxed())
+    }
+
+    ///
+    /// The `Quota` applying to `user`.
+    ///
+    /// Limits defined for the user itself take precedence over those of its organizations.
+    /// `max_sessions` defaults to `max_sessions_per_user`.
+    ///
+    pub fn quota(&self, user: &LoggedUser) -> Quota {
+        let configuration = self.engine.configuration();
+        let organizations: Vec<&Quota> = user
+            .organizations
+            .iter()
+            .filter_map(|organization| configuration.organizations.get(organization))
+            .collect();
+        let quota = user
+            .quota
+            .clone()
+            .or(Quota::most_permissive(&organizations));
+        Quota {
+            max_sessions: quota
+                .max_sessions
+                .or(Some(configuration.session.max_sessions_per_user)),
+            ..quota
+        }
+    }
+
+    // Fails if booking `duration` more today would exceed the `max_minutes_per_day` of `user`
+    fn check_booked_minutes(
+        &self,
+        user: &LoggedUser,
+        quota: &Quota,
+        duration: Duration,
     ) -> Result<()> {
-        if !user.has_admin_edit_rights() {
-            return Err(Error::Unauthorized());
+        if let Some(max_minutes) = quota.max_minutes_per_day {
+            let booked = self
+                .booked_minutes
+                .lock()
+                .map_err(|_| Error::Failure("Failed to acquire booked minutes lock".into()))?
+                .get(&user.id)
+                .filter(|booked| booked.day == today())
+                .map_or(0, |booked| booked.minutes);
+            if booked + duration.as_secs() / 60 > max_minutes {
+                return Err(Error::QuotaExceeded(format!(
+                    "maximum of {} session minutes per day",
+                    max_minutes
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    //
+    // Books `duration` more today for `user`. Fails if it would exceed the `max_minutes_per_day` of `quota`.
+    // Minutes are checked and booked under the same lock, so that concurrent requests can't both pass.
+    //
+    fn book_minutes(&self, user: &LoggedUser, quota: &Quota, duration: Duration) -> Result<()> {
+        let mut booked_minutes = self
+            .booked_minutes
+            .lock()
+            .map_err(|_| Error::Failure("Failed to acquire booked minutes lock".into()))?;
+        let booked = booked_minutes
+            .entry(user.id.clone())
+            .or_insert(BookedMinutes { day: 0, minutes: 0 });
+        if booked.day != today() {
+            *booked = BookedMinutes {
+                day: today(),
+                minutes: 0,
+            };
+        }
+        let minutes = booked.minutes + duration.as_secs() / 60;
+        if let Some(max_minutes) = quota.max_minutes_per_day {
+            if minutes > max_minutes {
+                return Err(Error::QuotaExceeded(format!(
+                    "maximum of {} session minutes per day",
+                    max_minutes
+                )));
+            }
         }
+        booked.minutes = minutes;
+        Ok(())
+    }
 
+    // Gives back minutes booked by `book_minutes` for a request that eventually failed
+    fn release_minutes(&self, user: &LoggedUser, duration: Duration) {
+        if let Ok(mut booked_minutes) = self.booked_minutes.lock() {
+            if let Some(booked) = booked_minutes
+                .get_mut(&user.id)
+                .filter(|booked| booked.day == today())
+            {
+                booked.minutes = booked.minutes.saturating_sub(duration.as_secs() / 60);
+            }
+        } else {
+            error!("Failed to acquire booked minutes lock");
+        }
+    }
+
+    ///
+    /// Creates a new session owned by `user`.
+    ///
+    /// If its pool is at capacity, the request is queued and admitted once capacity frees up.
+    /// Fails if the `quota` of `user` would be exceeded.
+    ///
+    pub async fn create_session(
+        &self,
+        user: &LoggedUser,
+        conf: SessionConfiguration,
+    ) -> Result<Admission> {
         if conf.duration.is_some() {
-            // Duration can only customized by users with proper rights
+            // Duration can only be customized by users with proper rights
             if !user.can_customize_duration() {
-                return Err(Error::Unauthorized());
+                return Err(Error::Forbidden(
+                    "customizing duration requires specific rights",
+                ));
             }
         }
         if conf.pool_affinity.is_some() {
-            // Duration can only customized by users with proper rights
+            // Pool affinity can only be customized by users with proper rights
             if !user.can_customize_pool_affinity() {
-                return Err(Error::Unauthorized());
+                return Err(Error::Forbidden(
+                    "customizing pool affinity requires specific rights",
+                ));
             }
         }
 
-        let session_id = session_id(id);
-        if self.get_session(user, &session_id)?.is_some() {
-            return Err(Error::Unauthorized());
+        // Hidden templates are reported as missing
+        let visible = matches!(
+            self.engine.list_templates().await?.get(&conf.template),
+            Some(template) if template.is_visible_to(Some(user))
+        );
+        if !visible {
+            return Err(Error::TemplateNotFound(conf.template));
         }
 
-        let template = conf.clone().template;
-        let result = new_runtime()?.block_on(self.engine.create_session(user, &session_id, conf));
+        let quota = self.quota(user);
+        if let Some(templates) = &quota.templates {
+            if !templates.contains(&conf.template) {
+                return Err(Error::QuotaExceeded(format!(
+                    "template {} is not allowed",
+                    conf.template
+                )));
+            }
+        }
+        // Held until the session is created or queued, so that concurrent requests can't exceed `max_sessions`
+        let _admitting = self.admitting.lock().await;
+        if let Some(max_sessions) = quota.max_sessions {
+            // Queued sessions count as well
+            let sessions =
+                self.list_user_sessions(user).await?.len() + self.queue.count(&user.id)?;
+            if sessions >= max_sessions {
+                return Err(Error::QuotaExceeded(format!(
+                    "maximum of {} concurrent sessions",
+                    max_sessions
+                )));
+            }
+        }
+        // Checked early so that requests are not queued in vain. Minutes are booked once admitted.
+        let duration = conf
+            .duration
+            .unwrap_or(self.engine.configuration().session.duration);
+        self.check_booked_minutes(user, &quota, duration)?;
 
-        info!("Created session {} with template {}", session_id, template);
+        let pool_affinity = pool_affinity(self.engine.configuration(), user, &conf);
+        // Requests already waiting for this pool come first
+        if !self.queue.is_waiting_for(&pool_affinity)? {
+            match self.create_admitted_session(user, conf.clone()).await {
+                Ok(id) => return Ok(Admission::Created { id }),
+                Err(Error::CapacityExhausted(_)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let id = self.queue.push(user.clone(), conf, pool_affinity)?;
+        info!("Queued {} for user {}", id, user.id);
+        self.list_queued_sessions(user)
+            .await?
+            .into_iter()
+            .find(|queued| queued.id == id)
+            .map(Admission::Queued)
+            .ok_or(Error::NotFound("queued session"))
+    }
+
+    //
+    // Creates a session whose request passed all checks. Returns the generated session id.
+    // Minutes are booked right before, as other sessions might have been created since the request was queued.
+    //
+    async fn create_admitted_session(
+        &self,
+        user: &LoggedUser,
+        conf: SessionConfiguration,
+    ) -> Result<String> {
+        let duration = conf
+            .duration
+            .unwrap_or(self.engine.configuration().session.duration);
+        self.book_minutes(user, &self.quota(user), duration)?;
+        let template = conf.clone().template;
+        let result = self.engine.create_session(user, conf).await;
+        if result.is_err() {
+            self.release_minutes(user, duration);
+        }
 
         match &result {
-            Ok(_session) => {
+            Ok(session_id) => {
+                info!("Created session {} with template {}", session_id, template);
                 if let Ok(mut sessions) = self.sessions.lock() {
-                    sessions.insert(session_id);
+                    sessions.insert(session_id.clone());
                 } else {
                     error!("Failed to acquire sessions lock");
                 }
                 self.metrics.inc_deploy_counter(&template);
             }
+            // Not a failure, the request will be queued
+            Err(Error::CapacityExhausted(_)) => (),
             Err(e) => {
                 self.metrics.inc_deploy_failures_counter(&template);
                 error!("Error during deployment {}", e);
@@ -292,37 +905,67 @@ impl Manager {
         result
     }
 
-    pub fn update_session(
+    pub async fn update_session(
         &self,
         id: &str,
         user: &LoggedUser,
         conf: SessionUpdateConfiguration,
     ) -> Result<()> {
-        if !user.has_admin_edit_rights() {
-            return Err(Error::Unauthorized());
-        }
-
         if conf.duration.is_some() {
-            // Duration can only customized by users with proper rights
+            // Duration can only be customized by users with proper rights
             if !user.can_customize_duration() {
-                return Err(Error::Unauthorized());
+                return Err(Error::Forbidden(
+                    "customizing duration requires specific rights",
+                ));
             }
         }
-        new_runtime()?.block_on(self.engine.update_session(&session_id(id), conf))
-    }
 
-    pub fn delete_session(&self, user: &LoggedUser, id: &str) -> Result<()> {
-        if !user.has_admin_edit_rights() {
-            return Err(Error::Unauthorized());
+        let session = self
+            .accessible_session(user, id, user.has_admin_edit_rights())
+            .await?
+            .ok_or(Error::NotFound("session"))?;
+        // Only extensions count against the quota of the owner. Admins are not limited when updating sessions of others.
+        let duration = conf
+            .duration
+            .unwrap_or(self.engine.configuration().session.duration);
+        let extension = duration.checked_sub(session.duration).unwrap_or_default();
+        let owned = session.user_id == user.id;
+        if owned {
+            self.book_minutes(user, &self.quota(user), extension)?;
+        }
+        let result = self.engine.update_session(id, conf).await;
+        if owned && result.is_err() {
+            self.release_minutes(user, extension);
         }
+        result
+    }
+
+    ///
+    /// Records some activity of session `id`, postponing its undeployment if idle.
+    ///
+    pub async fn record_activity(&self, user: &LoggedUser, id: &str) -> Result<()> {
+        self.accessible_session(user, id, user.has_admin_edit_rights())
+            .await?
+            .ok_or(Error::NotFound("session"))?;
+
+        self.activity
+            .lock()
+            .map_err(|_| Error::Failure("Failed to acquire activity lock".into()))?
+            .insert(id.to_string(), SystemTime::now());
+        Ok(())
+    }
+
+    pub async fn delete_session(&self, user: &LoggedUser, id: &str) -> Result<()> {
+        self.accessible_session(user, id, user.has_admin_edit_rights())
+            .await?
+            .ok_or(Error::NotFound("session"))?;
 
-        let session_id = session_id(id);
-        let result = new_runtime()?.block_on(self.engine.delete_session(&session_id));
+        let result = self.engine.delete_session(id).await;
         match &result {
             Ok(_) => {
                 self.metrics.inc_undeploy_counter();
                 if let Ok(mut sessions) = self.sessions.lock() {
-                    sessions.remove(session_id.as_str());
+                    sessions.remove(id);
                 } else {
                     error!("Failed to acquire sessions lock");
                 }
@@ -335,21 +978,482 @@ impl Manager {
         result
     }
 
+    // Queue
+
+    ///
+    /// Lists queued sessions of `user`, in admission order.
+    ///
+    pub async fn list_queued_sessions(&self, user: &LoggedUser) -> Result<Vec<QueuedSession>> {
+        let entries = self.queue.entries()?;
+        let sessions = self.engine.list_sessions().await?;
+        let mut positions: BTreeMap<String, usize> = BTreeMap::new();
+        let mut queued_sessions = Vec::new();
+        for entry in entries {
+            let position = positions.entry(entry.pool_affinity.clone()).or_insert(0);
+            *position += 1;
+            if entry.user.id == user.id {
+                let eta = self.eta(&sessions, &entry.pool_affinity, *position).await?;
+                queued_sessions.push(QueuedSession {
+                    id: entry.id,
+                    user_id: entry.user.id,
+                    template: entry.conf.template,
+                    pool_affinity: entry.pool_affinity,
+                    position: *position,
+                    eta,
+                });
+            }
+        }
+        Ok(queued_sessions)
+    }
+
+    // Estimates the seconds before the request at `position` in the queue of `pool_id` is admitted.
+    // Assumes hosted sessions run until they expire.
+    async fn eta(
+        &self,
+        sessions: &BTreeMap<String, Session>,
+        pool_id: &str,
+        position: usize,
+    ) -> Result<Option<u64>> {
+        let pool = match self.engine.get_pool(pool_id).await? {
+            Some(pool) => pool,
+            None => return Ok(None),
+        };
+        if position <= pool.available {
+            return Ok(Some(0));
+        }
+        let mut remaining: Vec<u64> = sessions
+            .values()
+            .filter(|session| pool.hosts(session))
+            .map(|session| {
+                let elapsed = session
+                    .pod
+                    .start_time
+                    .and_then(|start_time| start_time.elapsed().ok())
+                    .unwrap_or_default();
+                session
+                    .duration
+                    .checked_sub(elapsed)
+                    .unwrap_or_default()
+                    .as_secs()
+            })
+            .collect();
+        remaining.sort_unstable();
+        Ok(remaining.get(position - pool.available - 1).copied())
+    }
+
+    ///
+    /// Streams queued sessions of `user`, each time the queue changes.
+    ///
+    pub async fn watch_queued_sessions(
+        &self,
+        user: &LoggedUser,
+    ) -> Result<BoxStream<'static, Vec<QueuedSession>>> {
+        // Subscribe first so that no change is missed in between
+        let changes = receiver_stream(self.queue.subscribe());
+        let current = self.list_queued_sessions(user).await?;
+        let manager = self.clone();
+        let user = user.clone();
+        let updates = changes.filter_map(move |_| {
+            let manager = manager.clone();
+            let user = user.clone();
+            async move {
+                match manager.list_queued_sessions(&user).await {
+                    Ok(queued_sessions) => Some(queued_sessions),
+                    Err(err) => {
+                        warn!("Failed to list queued sessions: {}", err);
+                        None
+                    }
+                }
+            }
+        });
+        Ok(stream::once(future::ready(current)).chain(updates).boxed())
+    }
+
+    ///
+    /// Cancels queued session `id`, if owned by `user` or if `user` has admin edit rights.
+    ///
+    /// Waits for any ongoing admission, so that admitted sessions are reported as missing rather than cancelled.
+    ///
+    pub async fn cancel_queued_session(&self, user: &LoggedUser, id: &str) -> Result<()> {
+        let _admitting = self.admitting.lock().await;
+        let entry = self
+            .queue
+            .entries()?
+            .into_iter()
+            .find(|entry| entry.id == id)
+            .ok_or(Error::NotFound("queued session"))?;
+        if entry.user.id != user.id && !user.has_admin_edit_rights() {
+            return Err(Error::NotFound("queued session"));
+        }
+        self.queue
+            .remove(id)?
+            .ok_or(Error::NotFound("queued session"))?;
+        info!("Cancelled {}", id);
+        Ok(())
+    }
+
+    // Workspaces
+
+    // Fails unless the workspace of `user_id` belongs to `user`, or `admin` allows `user` to access others workspaces.
+    // Workspaces of others are reported as missing, unless `user` could at least read them.
+    fn check_workspace_access(user: &LoggedUser, user_id: &str, admin: bool) -> Result<()> {
+        if user.id == user_id || admin {
+            Ok(())
+        } else if user.has_admin_read_rights() {
+            Err(Error::Forbidden("admin edit rights required"))
+        } else {
+            Err(Error::NotFound("workspace"))
+        }
+    }
+
+    pub async fn get_workspace(
+        &self,
+        user: &LoggedUser,
+        user_id: &str,
+    ) -> Result<Option<Workspace>> {
+        Manager::check_workspace_access(user, user_id, user.has_admin_read_rights())?;
+        self.engine.get_workspace(user_id).await
+    }
+
+    pub async fn list_workspaces(&self, user: &LoggedUser) -> Result<BTreeMap<String, Workspace>> {
+        if user.has_admin_read_rights() {
+            self.engine.list_workspaces().await
+        } else {
+            Err(Error::Forbidden("admin read rights required"))
+        }
+    }
+
+    /// Archives the workspace of `user_id`, as a gzipped tarball
+    pub async fn snapshot_workspace(&self, user: &LoggedUser, user_id: &str) -> Result<Vec<u8>> {
+        Manager::check_workspace_access(user, user_id, user.has_admin_read_rights())?;
+        self.engine.snapshot_workspace(user_id).await
+    }
+
+    ///
+    /// Deletes the workspace of `user_id`, along with its content.
+    ///
+    /// Fails if some sessions of `user_id` might still use it.
+    ///
+    pub async fn delete_workspace(&self, user: &LoggedUser, user_id: &str) -> Result<()> {
+        Manager::check_workspace_access(user, user_id, user.has_admin_edit_rights())?;
+        let in_use = self
+            .engine
+            .list_sessions()
+            .await?
+            .values()
+            .any(|session| session.user_id == user_id);
+        if in_use {
+            return Err(Error::Conflict(
+                "workspace is used by running sessions".to_string(),
+            ));
+        }
+        self.engine.delete_workspace(user_id).await
+    }
+
     // Pools
 
-    pub fn get_pool(&self, user: &LoggedUser, pool_id: &str) -> Result<Option<Pool>> {
+    pub async fn get_pool(&self, user: &LoggedUser, pool_id: &str) -> Result<Option<Pool>> {
         if !user.has_admin_read_rights() {
-            return Err(Error::Unauthorized());
+            return Err(Error::Forbidden("admin read rights required"));
         }
 
-        new_runtime()?.block_on(self.engine.get_pool(&pool_id))
+        self.engine.get_pool(pool_id).await
     }
 
-    pub fn list_pools(&self, user: &LoggedUser) -> Result<BTreeMap<String, Pool>> {
+    pub async fn list_pools(&self, user: &LoggedUser) -> Result<BTreeMap<String, Pool>> {
+        if !user.has_admin_read_rights() {
+            return Err(Error::Forbidden("admin read rights required"));
+        }
+
+        self.engine.list_pools().await
+    }
+
+    // Builds
+
+    pub async fn get_build(&self, user: &LoggedUser, id: &str) -> Result<Option<Build>> {
         if !user.has_admin_read_rights() {
-            return Err(Error::Unauthorized());
+            return Err(Error::Forbidden("admin read rights required"));
+        }
+
+        self.engine.get_build(id).await
+    }
+
+    pub async fn list_builds(&self, user: &LoggedUser) -> Result<BTreeMap<String, Build>> {
+        if !user.has_admin_read_rights() {
+            return Err(Error::Forbidden("admin read rights required"));
+        }
+
+        self.engine.list_builds().await
+    }
+
+    ///
+    /// Starts building a new image of template `template_id`, replacing its image on success.
+    ///
+    pub async fn create_build(
+        &self,
+        user: &LoggedUser,
+        template_id: &str,
+        conf: BuildConfiguration,
+    ) -> Result<String> {
+        if !user.has_admin_edit_rights() {
+            return Err(Error::Forbidden("admin edit rights required"));
+        }
+
+        let templates = self.engine.list_templates().await?;
+        let template = templates
+            .get(template_id)
+            .ok_or_else(|| Error::TemplateNotFound(template_id.to_string()))?;
+        let repository = build_repository(template, &conf)?;
+        self.engine.create_build(template_id, repository).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{Configuration, Secrets, DEFAULT_TEMPLATE_HISTORY_SIZE},
+        memory::MemoryEngine,
+        types::SessionDefaults,
+    };
+
+    fn template(image: &str) -> Template {
+        let mut tags = BTreeMap::new();
+        tags.insert(Template::PUBLIC_TAG.to_string(), "true".to_string());
+        Template {
+            name: image.to_string(),
+            image: image.to_string(),
+            description: "".to_string(),
+            repository: None,
+            reference: None,
+            tags: Some(tags),
+            runtime: None,
+        }
+    }
+
+    // Sessions last 60 minutes by default, and up to 120 minutes
+    fn manager(templates: &[(&str, Template)], capacity: usize) -> Manager {
+        let engine = MemoryEngine::with_configuration(
+            Configuration {
+                github_client_id: "client-id".to_string(),
+                session: SessionDefaults {
+                    duration: Duration::from_secs(60 * 60),
+                    max_duration: Duration::from_secs(120 * 60),
+                    pool_affinity: "default".to_string(),
+                    max_sessions_per_pod: capacity,
+                    max_sessions_per_user: 5,
+                    resources: Default::default(),
+                    max_resources: Default::default(),
+                    idle_timeout: None,
+                    expiry_warning: Duration::from_secs(5 * 60),
+                },
+                organizations: BTreeMap::new(),
+                workspace: None,
+                build: None,
+                template_history_size: DEFAULT_TEMPLATE_HISTORY_SIZE,
+            },
+            Secrets {
+                github_client_secret: "client-secret".to_string(),
+            },
+            templates
+                .iter()
+                .map(|(id, template)| (id.to_string(), template.clone()))
+                .collect(),
+        );
+        Manager::new(Arc::new(engine)).unwrap()
+    }
+
+    fn user(id: &str, quota: Quota) -> LoggedUser {
+        LoggedUser {
+            id: id.to_string(),
+            admin: false,
+            organizations: vec![],
+            pool_affinity: None,
+            can_customize_duration: true,
+            can_customize_pool_affinity: false,
+            quota,
+        }
+    }
+
+    fn conf(template: &str) -> SessionConfiguration {
+        SessionConfiguration {
+            template: template.to_string(),
+            template_version: None,
+            duration: None,
+            pool_affinity: None,
+            repository: None,
+            reference: None,
         }
+    }
+
+    #[tokio::test]
+    async fn sessions_are_queued_then_admitted() {
+        let manager = manager(&[("template", template("image"))], 1);
+        let alice = user("alice", Quota::default());
+        let bob = user("bob", Quota::default());
+
+        let id = match manager.create_session(&alice, conf("template")).await {
+            Ok(Admission::Created { id }) => id,
+            other => panic!("unexpected admission {:?}", other),
+        };
+        let queued = match manager.create_session(&bob, conf("template")).await {
+            Ok(Admission::Queued(queued)) => queued,
+            other => panic!("unexpected admission {:?}", other),
+        };
+        assert_eq!(queued.user_id, "bob");
+        assert_eq!(queued.pool_affinity, "default");
+        assert_eq!(queued.position, 1);
+        assert_eq!(manager.list_queued_sessions(&bob).await.unwrap().len(), 1);
+        assert!(manager
+            .list_queued_sessions(&alice)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Still at capacity
+        manager.admit_queued_sessions().await;
+        assert_eq!(manager.list_queued_sessions(&bob).await.unwrap().len(), 1);
+
+        manager.delete_session(&alice, &id).await.unwrap();
+        manager.admit_queued_sessions().await;
+        assert!(manager.list_queued_sessions(&bob).await.unwrap().is_empty());
+        let sessions = manager.list_user_sessions(&bob).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].template_id, "template");
+    }
+
+    #[tokio::test]
+    async fn queued_sessions_can_be_cancelled_by_their_owner() {
+        let manager = manager(&[("template", template("image"))], 0);
+        let alice = user("alice", Quota::default());
+        let bob = user("bob", Quota::default());
+
+        let queued = match manager.create_session(&alice, conf("template")).await {
+            Ok(Admission::Queued(queued)) => queued,
+            other => panic!("unexpected admission {:?}", other),
+        };
+        assert!(matches!(
+            manager.cancel_queued_session(&bob, &queued.id).await,
+            Err(Error::NotFound("queued session"))
+        ));
+        manager
+            .cancel_queued_session(&alice, &queued.id)
+            .await
+            .unwrap();
+        assert!(manager
+            .list_queued_sessions(&alice)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            manager.cancel_queued_session(&alice, &queued.id).await,
+            Err(Error::NotFound("queued session"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn quotas_are_enforced() {
+        let manager = manager(&[("template", template("image"))], 2);
+        let alice = user(
+            "alice",
+            Quota {
+                max_sessions: Some(1),
+                ..Default::default()
+            },
+        );
+        manager
+            .create_session(&alice, conf("template"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            manager.create_session(&alice, conf("template")).await,
+            Err(Error::QuotaExceeded(_))
+        ));
+
+        let bob = user(
+            "bob",
+            Quota {
+                templates: Some(vec!["other".to_string()]),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            manager.create_session(&bob, conf("template")).await,
+            Err(Error::QuotaExceeded(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn booked_minutes_are_checked_again_at_admission() {
+        let manager = manager(&[("template", template("image"))], 1);
+        let alice = user(
+            "alice",
+            Quota {
+                max_minutes_per_day: Some(120),
+                ..Default::default()
+            },
+        );
+
+        let id = match manager.create_session(&alice, conf("template")).await {
+            Ok(Admission::Created { id }) => id,
+            other => panic!("unexpected admission {:?}", other),
+        };
+        // 60 minutes booked, 60 more fit
+        assert!(matches!(
+            manager.create_session(&alice, conf("template")).await,
+            Ok(Admission::Queued(_))
+        ));
+        // Extending the first session books 30 more minutes
+        manager
+            .update_session(
+                &id,
+                &alice,
+                SessionUpdateConfiguration {
+                    duration: Some(Duration::from_secs(90 * 60)),
+                },
+            )
+            .await
+            .unwrap();
+
+        manager.delete_session(&alice, &id).await.unwrap();
+        manager.admit_queued_sessions().await;
+        // The queued request no longer fits and is dropped
+        assert!(manager
+            .list_queued_sessions(&alice)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(manager.list_user_sessions(&alice).await.unwrap().is_empty());
+        // Minutes stay booked once sessions are deleted
+        assert!(matches!(
+            manager.create_session(&alice, conf("template")).await,
+            Err(Error::QuotaExceeded(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn hidden_templates_are_reported_missing() {
+        let mut restricted = template("image");
+        restricted.tags = Some(
+            vec![(Template::USERS_TAG.to_string(), "bob".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let manager = manager(&[("restricted", restricted)], 1);
+        let alice = user("alice", Quota::default());
+        let bob = user("bob", Quota::default());
 
-        new_runtime()?.block_on(self.clone().engine.list_pools())
+        assert!(matches!(
+            manager.create_session(&alice, conf("restricted")).await,
+            Err(Error::TemplateNotFound(_))
+        ));
+        assert!(matches!(
+            manager.create_session(&alice, conf("unknown")).await,
+            Err(Error::TemplateNotFound(_))
+        ));
+        assert!(matches!(
+            manager.create_session(&bob, conf("restricted")).await,
+            Ok(Admission::Created { .. })
+        ));
     }
 }
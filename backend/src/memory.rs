@@ -0,0 +1,498 @@
+//! In-memory `SessionBackend`, allowing to run the API without a k8s cluster
+use crate::{
+    backend::{
+        pool_affinity, receiver_stream, session_id_prefix, session_repository, session_resources,
+        session_template, subdomain, CollectedGarbage, Configuration, Environment, Secrets,
+        SessionBackend,
+    },
+    error::{Error, Result},
+    types::{
+        remaining_time, Build, LoggedUser, Node, Phase, Pod, Pool, Repository, Session,
+        SessionConfiguration, SessionEvent, SessionUpdateConfiguration, Template, TemplateVersion,
+        User, UserConfiguration, UserUpdateConfiguration, Workspace,
+    },
+};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::SystemTime,
+};
+use tokio::sync::broadcast::{self, Sender};
+
+const LOCAL_HOSTNAME: &str = "localhost";
+
+//
+// Loads all templates from a directory, one YAML file per template.
+// Mimics the `playground-templates` ConfigMap where keys are file names.
+//
+fn load_templates(path: &str) -> Result<BTreeMap<String, Template>> {
+    let mut templates = BTreeMap::new();
+    for entry in fs::read_dir(path).map_err(|err| Error::Failure(err.into()))? {
+        let path = entry.map_err(|err| Error::Failure(err.into()))?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let id = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(Error::MissingData("template file name"))?
+            .to_string();
+        let content = fs::read_to_string(&path).map_err(|err| Error::Failure(err.into()))?;
+        match serde_yaml::from_str::<Template>(&content) {
+            Ok(template) => {
+                let problems = template.validate();
+                if problems.is_empty() {
+                    templates.insert(id, template);
+                } else {
+                    log::error!("Invalid template {}: {:?}", id, problems);
+                }
+            }
+            Err(err) => log::error!("Error while parsing template {}: {}", id, err),
+        }
+    }
+    Ok(templates)
+}
+
+// Stored sessions don't track time, `remaining` is computed when they are read
+fn with_remaining(session: &Session) -> Session {
+    Session {
+        remaining: remaining_time(session.pod.start_time, session.duration),
+        ..session.clone()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| Error::Failure("Failed to acquire lock".into()))
+}
+
+pub struct MemoryEngine {
+    env: Environment,
+    configuration: Configuration,
+    secrets: Secrets,
+    // Versions kept of each template, oldest first
+    templates: Mutex<BTreeMap<String, Vec<TemplateVersion>>>,
+    pools: BTreeMap<String, Pool>,
+    users: Mutex<BTreeMap<String, User>>,
+    sessions: Mutex<BTreeMap<String, Session>>,
+    workspaces: Mutex<BTreeMap<String, Workspace>>,
+    // Suffix of the next generated session id
+    session_counter: AtomicUsize,
+    session_events: Sender<SessionEvent>,
+}
+
+impl MemoryEngine {
+    ///
+    /// Creates a new `MemoryEngine` with a single pool made of one node.
+    ///
+    /// Templates are read from the directory pointed by `PLAYGROUND_TEMPLATES_PATH`, if any.
+    ///
+    pub fn new() -> Result<Self> {
+        let templates = match env::var("PLAYGROUND_TEMPLATES_PATH") {
+            Ok(path) => load_templates(&path)?,
+            Err(_) => BTreeMap::new(),
+        };
+        Ok(MemoryEngine::with_configuration(
+            Configuration::from_env()?,
+            Secrets::from_env()?,
+            templates,
+        ))
+    }
+
+    // Creates a new `MemoryEngine` with a single pool made of one node, serving `templates`
+    pub(crate) fn with_configuration(
+        configuration: Configuration,
+        secrets: Secrets,
+        templates: BTreeMap<String, Template>,
+    ) -> Self {
+        let pool_id = configuration.session.pool_affinity.clone();
+        let mut pools = BTreeMap::new();
+        pools.insert(
+            pool_id.clone(),
+            Pool::new(
+                pool_id,
+                Some("local".to_string()),
+                vec![Node {
+                    hostname: LOCAL_HOSTNAME.to_string(),
+                }],
+                configuration.session.max_sessions_per_pod,
+            ),
+        );
+
+        let (session_events, _) = broadcast::channel(64);
+        MemoryEngine {
+            env: Environment {
+                secured: false,
+                host: LOCAL_HOSTNAME.to_string(),
+                namespace: "default".to_string(),
+            },
+            configuration,
+            secrets,
+            templates: Mutex::new(
+                templates
+                    .into_iter()
+                    .map(|(id, template)| {
+                        (
+                            id,
+                            vec![TemplateVersion {
+                                version: 1,
+                                template,
+                            }],
+                        )
+                    })
+                    .collect(),
+            ),
+            pools,
+            users: Mutex::new(BTreeMap::new()),
+            sessions: Mutex::new(BTreeMap::new()),
+            workspaces: Mutex::new(BTreeMap::new()),
+            session_counter: AtomicUsize::new(0),
+            session_events,
+        }
+    }
+
+    fn notify(&self, event: SessionEvent) {
+        // Fails only when there is no receiver
+        let _ = self.session_events.send(event);
+    }
+}
+
+#[async_trait]
+impl SessionBackend for MemoryEngine {
+    fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    fn configuration(&self) -> &Configuration {
+        &self.configuration
+    }
+
+    fn secrets(&self) -> &Secrets {
+        &self.secrets
+    }
+
+    async fn list_templates(&self) -> Result<BTreeMap<String, Template>> {
+        Ok(lock(&self.templates)?
+            .iter()
+            .filter_map(|(id, versions)| {
+                versions
+                    .last()
+                    .map(|version| (id.clone(), version.template.clone()))
+            })
+            .collect())
+    }
+
+    async fn update_template(&self, id: &str, template: Template) -> Result<()> {
+        let mut templates = lock(&self.templates)?;
+        let versions = templates.entry(id.to_string()).or_default();
+        let version = versions.last().map_or(1, |latest| latest.version + 1);
+        versions.push(TemplateVersion { version, template });
+        let size = self.configuration.template_history_size;
+        if versions.len() > size {
+            versions.drain(..versions.len() - size);
+        }
+        Ok(())
+    }
+
+    async fn delete_template(&self, id: &str) -> Result<()> {
+        lock(&self.templates)?
+            .remove(id)
+            .map(|_| ())
+            .ok_or_else(|| Error::TemplateNotFound(id.to_string()))
+    }
+
+    async fn list_template_versions(&self, id: &str) -> Result<Vec<TemplateVersion>> {
+        lock(&self.templates)?
+            .get(id)
+            .cloned()
+            .ok_or_else(|| Error::TemplateNotFound(id.to_string()))
+    }
+
+    async fn get_user(&self, id: &str) -> Result<Option<User>> {
+        Ok(lock(&self.users)?.get(id).cloned())
+    }
+
+    async fn list_users(&self) -> Result<BTreeMap<String, User>> {
+        Ok(lock(&self.users)?.clone())
+    }
+
+    async fn create_user(&self, id: String, conf: UserConfiguration) -> Result<()> {
+        lock(&self.users)?.insert(id, conf.into());
+        Ok(())
+    }
+
+    async fn update_user(&self, id: String, conf: UserUpdateConfiguration) -> Result<()> {
+        lock(&self.users)?.insert(id, conf.into());
+        Ok(())
+    }
+
+    async fn delete_user(&self, id: String) -> Result<()> {
+        lock(&self.users)?
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(Error::NotFound("user"))
+    }
+
+    async fn get_session(&self, id: &str) -> Result<Option<Session>> {
+        Ok(lock(&self.sessions)?.get(id).map(with_remaining))
+    }
+
+    async fn list_sessions(&self) -> Result<BTreeMap<String, Session>> {
+        Ok(lock(&self.sessions)?
+            .iter()
+            .map(|(id, session)| (id.clone(), with_remaining(session)))
+            .collect())
+    }
+
+    async fn create_session(
+        &self,
+        user: &LoggedUser,
+        conf: SessionConfiguration,
+    ) -> Result<String> {
+        let pool_id = pool_affinity(&self.configuration, user, &conf);
+        let TemplateVersion { version, template } =
+            session_template(self.list_template_versions(&conf.template).await?, &conf)?;
+        let resources = session_resources(&self.configuration, &template)?;
+        let repository = session_repository(&template, &conf)?;
+
+        let mut sessions = lock(&self.sessions)?;
+        let pool = self
+            .pools
+            .get(&pool_id)
+            .cloned()
+            .ok_or(Error::NotFound("pool"))?
+            .with_sessions(sessions.values());
+        if pool.available == 0 {
+            return Err(Error::CapacityExhausted(pool_id));
+        }
+        let node = pool
+            .nodes
+            .first()
+            .ok_or(Error::MissingData("empty vec of nodes"))?;
+        let session_id = format!(
+            "{}{}",
+            session_id_prefix(&user.id),
+            self.session_counter.fetch_add(1, Ordering::Relaxed)
+        );
+        let session = Session {
+            id: session_id.clone(),
+            user_id: user.id.clone(),
+            template_id: conf.template.clone(),
+            template_version: version,
+            template,
+            url: subdomain(&self.env.host, &session_id),
+            pod: Pod {
+                phase: Phase::Running,
+                reason: "".to_string(),
+                message: "".to_string(),
+                start_time: Some(SystemTime::now()),
+                container: None,
+            },
+            duration: conf.duration.unwrap_or(self.configuration.session.duration),
+            node: node.hostname.clone(),
+            pool_affinity: pool_id,
+            resources,
+            remaining: None,
+            repository,
+        };
+        sessions.insert(session_id.clone(), session.clone());
+        if let Some(workspace) = &self.configuration.workspace {
+            lock(&self.workspaces)?.insert(
+                user.id.clone(),
+                Workspace {
+                    user_id: user.id.clone(),
+                    size: workspace.size.clone(),
+                    phase: "Bound".to_string(),
+                    last_used: Some(SystemTime::now()),
+                },
+            );
+        }
+        self.notify(SessionEvent::Updated {
+            id: session_id.clone(),
+            session: Box::new(session),
+        });
+
+        Ok(session_id)
+    }
+
+    async fn update_session(
+        &self,
+        session_id: &str,
+        conf: SessionUpdateConfiguration,
+    ) -> Result<()> {
+        let duration = conf.duration.unwrap_or(self.configuration.session.duration);
+        if duration > self.configuration.session.max_duration {
+            return Err(Error::Validation(format!(
+                "duration exceeds the maximum of {} minutes",
+                self.configuration.session.max_duration.as_secs() / 60
+            )));
+        }
+        let mut sessions = lock(&self.sessions)?;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or(Error::NotFound("session"))?;
+        session.duration = duration;
+        self.notify(SessionEvent::Updated {
+            id: session_id.to_string(),
+            session: Box::new(with_remaining(session)),
+        });
+
+        Ok(())
+    }
+
+    async fn delete_session(&self, id: &str) -> Result<()> {
+        let session = lock(&self.sessions)?
+            .remove(id)
+            .ok_or(Error::NotFound("session"))?;
+        self.notify(SessionEvent::Deleted {
+            id: id.to_string(),
+            user_id: session.user_id,
+        });
+        Ok(())
+    }
+
+    fn session_events(&self) -> BoxStream<'static, SessionEvent> {
+        receiver_stream(self.session_events.subscribe())
+    }
+
+    async fn get_pool(&self, id: &str) -> Result<Option<Pool>> {
+        let sessions = lock(&self.sessions)?;
+        Ok(self
+            .pools
+            .get(id)
+            .map(|pool| pool.clone().with_sessions(sessions.values())))
+    }
+
+    async fn list_pools(&self) -> Result<BTreeMap<String, Pool>> {
+        let sessions = lock(&self.sessions)?;
+        Ok(self
+            .pools
+            .iter()
+            .map(|(id, pool)| (id.clone(), pool.clone().with_sessions(sessions.values())))
+            .collect())
+    }
+
+    async fn get_workspace(&self, user_id: &str) -> Result<Option<Workspace>> {
+        Ok(lock(&self.workspaces)?.get(user_id).cloned())
+    }
+
+    async fn list_workspaces(&self) -> Result<BTreeMap<String, Workspace>> {
+        Ok(lock(&self.workspaces)?.clone())
+    }
+
+    async fn snapshot_workspace(&self, _user_id: &str) -> Result<Vec<u8>> {
+        // Workspaces have no content here
+        Err(Error::Failure(
+            "Workspace snapshots are not supported by the memory backend".into(),
+        ))
+    }
+
+    async fn delete_workspace(&self, user_id: &str) -> Result<()> {
+        lock(&self.workspaces)?
+            .remove(user_id)
+            .map(|_| ())
+            .ok_or(Error::NotFound("workspace"))
+    }
+
+    async fn get_build(&self, _id: &str) -> Result<Option<Build>> {
+        Ok(None)
+    }
+
+    async fn list_builds(&self) -> Result<BTreeMap<String, Build>> {
+        Ok(BTreeMap::new())
+    }
+
+    async fn create_build(&self, _template_id: &str, _repository: Repository) -> Result<String> {
+        // There is no cluster to build images in
+        Err(Error::Failure(
+            "Image builds are not supported by the memory backend".into(),
+        ))
+    }
+
+    async fn collect_garbage(&self) -> Result<CollectedGarbage> {
+        // Sessions are updated atomically, nothing can be left behind
+        Ok(CollectedGarbage::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::DEFAULT_TEMPLATE_HISTORY_SIZE;
+    use crate::types::SessionDefaults;
+    use std::time::Duration;
+
+    fn template(image: &str) -> Template {
+        let mut tags = BTreeMap::new();
+        tags.insert(Template::PUBLIC_TAG.to_string(), "true".to_string());
+        Template {
+            name: image.to_string(),
+            image: image.to_string(),
+            description: "".to_string(),
+            repository: None,
+            reference: None,
+            tags: Some(tags),
+            runtime: None,
+        }
+    }
+
+    fn engine(templates: &[(&str, Template)], capacity: usize) -> MemoryEngine {
+        let configuration = Configuration {
+            github_client_id: "client-id".to_string(),
+            session: SessionDefaults {
+                duration: Duration::from_secs(60 * 60),
+                max_duration: Duration::from_secs(120 * 60),
+                pool_affinity: "default".to_string(),
+                max_sessions_per_pod: capacity,
+                max_sessions_per_user: 5,
+                resources: Default::default(),
+                max_resources: Default::default(),
+                idle_timeout: None,
+                expiry_warning: Duration::from_secs(5 * 60),
+            },
+            organizations: BTreeMap::new(),
+            workspace: None,
+            build: None,
+            template_history_size: DEFAULT_TEMPLATE_HISTORY_SIZE,
+        };
+        MemoryEngine::with_configuration(
+            configuration,
+            Secrets {
+                github_client_secret: "client-secret".to_string(),
+            },
+            templates
+                .iter()
+                .map(|(id, template)| (id.to_string(), template.clone()))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn template_history_is_bounded() {
+        let engine = engine(&[("template", template("image-1"))], 1);
+        for i in 2..=DEFAULT_TEMPLATE_HISTORY_SIZE + 2 {
+            engine
+                .update_template("template", template(&format!("image-{}", i)))
+                .await
+                .unwrap();
+        }
+        let versions = engine.list_template_versions("template").await.unwrap();
+        assert_eq!(versions.len(), DEFAULT_TEMPLATE_HISTORY_SIZE);
+        assert_eq!(versions.first().unwrap().version, 3);
+        assert_eq!(
+            versions.last().unwrap().version,
+            DEFAULT_TEMPLATE_HISTORY_SIZE as u64 + 2
+        );
+        assert_eq!(
+            engine.list_templates().await.unwrap()["template"].image,
+            format!("image-{}", DEFAULT_TEMPLATE_HISTORY_SIZE + 2)
+        );
+    }
+}
@@ -9,10 +9,12 @@ pub struct Metrics {
     undeploy_counter: IntCounterVec,
     undeploy_failures_counter: IntCounterVec,
     deploy_duration: HistogramVec,
+    garbage_collected_counter: IntCounterVec,
 }
 
 impl Metrics {
     const TEMPLATE_LABEL: &'static str = "template";
+    const RESOURCE_LABEL: &'static str = "resource";
 
     pub fn new() -> Result<Self, Error> {
         let opts = histogram_opts!(
@@ -41,6 +43,13 @@ impl Metrics {
                 &[],
             )?,
             deploy_duration: HistogramVec::new(opts, &[])?,
+            garbage_collected_counter: IntCounterVec::new(
+                opts!(
+                    "garbage_collected_counter",
+                    "Count of orphan resources deleted"
+                ),
+                &[Self::RESOURCE_LABEL],
+            )?,
         })
     }
 
@@ -51,6 +60,7 @@ impl Metrics {
         registry.register(Box::new(self.undeploy_counter))?;
         registry.register(Box::new(self.undeploy_failures_counter))?;
         registry.register(Box::new(self.deploy_duration))?;
+        registry.register(Box::new(self.garbage_collected_counter))?;
         Ok(())
     }
 }
@@ -80,4 +90,10 @@ impl Metrics {
             .with_label_values(&[])
             .observe(duration);
     }
+
+    pub fn inc_garbage_collected_counter(&self, resource: &str, count: usize) {
+        self.garbage_collected_counter
+            .with_label_values(&[resource])
+            .inc_by(count as u64);
+    }
 }
@@ -0,0 +1,210 @@
+//! Session creation requests waiting for pool capacity
+use crate::{
+    error::{Error, Result},
+    types::{LoggedUser, SessionConfiguration},
+};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+use tokio::sync::broadcast;
+
+/// A session creation request, as queued
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub id: String,
+    pub user: LoggedUser,
+    pub conf: SessionConfiguration,
+    /// Pool the session will be created on
+    pub pool_affinity: String,
+}
+
+///
+/// A FIFO of session creation requests.
+///
+/// Each change is notified to `subscribe`rs.
+///
+#[derive(Clone)]
+pub struct AdmissionQueue {
+    entries: Arc<Mutex<VecDeque<Entry>>>,
+    // Suffix of the next generated entry id
+    counter: Arc<AtomicUsize>,
+    events: broadcast::Sender<()>,
+}
+
+impl AdmissionQueue {
+    const EVENTS_CAPACITY: usize = 16;
+
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(Self::EVENTS_CAPACITY);
+        AdmissionQueue {
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+            counter: Arc::new(AtomicUsize::new(0)),
+            events,
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, VecDeque<Entry>>> {
+        self.entries
+            .lock()
+            .map_err(|_| Error::Failure("Failed to acquire queue lock".into()))
+    }
+
+    fn notify(&self) {
+        // Fails only when there is no receiver
+        let _ = self.events.send(());
+    }
+
+    /// Appends a new `Entry`. Returns its generated id.
+    pub fn push(
+        &self,
+        user: LoggedUser,
+        conf: SessionConfiguration,
+        pool_affinity: String,
+    ) -> Result<String> {
+        let id = format!("queued-{}", self.counter.fetch_add(1, Ordering::Relaxed));
+        self.lock()?.push_back(Entry {
+            id: id.clone(),
+            user,
+            conf,
+            pool_affinity,
+        });
+        self.notify();
+        Ok(id)
+    }
+
+    pub fn remove(&self, id: &str) -> Result<Option<Entry>> {
+        let mut entries = self.lock()?;
+        let entry = entries
+            .iter()
+            .position(|entry| entry.id == id)
+            .and_then(|index| entries.remove(index));
+        drop(entries);
+        if entry.is_some() {
+            self.notify();
+        }
+        Ok(entry)
+    }
+
+    /// All entries, in admission order
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        Ok(self.lock()?.iter().cloned().collect())
+    }
+
+    /// `true` if some entry waits for `pool_affinity`
+    pub fn is_waiting_for(&self, pool_affinity: &str) -> Result<bool> {
+        Ok(self
+            .lock()?
+            .iter()
+            .any(|entry| entry.pool_affinity == pool_affinity))
+    }
+
+    /// Number of entries queued by `user_id`
+    pub fn count(&self, user_id: &str) -> Result<usize> {
+        Ok(self
+            .lock()?
+            .iter()
+            .filter(|entry| entry.user.id == user_id)
+            .count())
+    }
+
+    /// Notified of all changes happening from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<()> {
+        self.events.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Quota;
+
+    fn user(id: &str) -> LoggedUser {
+        LoggedUser {
+            id: id.to_string(),
+            admin: false,
+            organizations: vec![],
+            pool_affinity: None,
+            can_customize_duration: false,
+            can_customize_pool_affinity: false,
+            quota: Quota::default(),
+        }
+    }
+
+    fn conf() -> SessionConfiguration {
+        SessionConfiguration {
+            template: "template".to_string(),
+            template_version: None,
+            duration: None,
+            pool_affinity: None,
+            repository: None,
+            reference: None,
+        }
+    }
+
+    fn ids(queue: &AdmissionQueue) -> Vec<String> {
+        queue
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    #[test]
+    fn entries_are_kept_in_order() {
+        let queue = AdmissionQueue::new();
+        let first = queue
+            .push(user("alice"), conf(), "pool-1".to_string())
+            .unwrap();
+        let second = queue
+            .push(user("bob"), conf(), "pool-2".to_string())
+            .unwrap();
+        let third = queue
+            .push(user("alice"), conf(), "pool-1".to_string())
+            .unwrap();
+
+        assert_eq!(ids(&queue), vec![first, second, third]);
+        assert_eq!(queue.count("alice").unwrap(), 2);
+        assert_eq!(queue.count("carol").unwrap(), 0);
+        assert!(queue.is_waiting_for("pool-2").unwrap());
+        assert!(!queue.is_waiting_for("pool-3").unwrap());
+    }
+
+    #[test]
+    fn entries_can_be_removed() {
+        let queue = AdmissionQueue::new();
+        let first = queue
+            .push(user("alice"), conf(), "pool".to_string())
+            .unwrap();
+        let second = queue.push(user("bob"), conf(), "pool".to_string()).unwrap();
+
+        let removed = queue.remove(&first).unwrap().unwrap();
+        assert_eq!(removed.user.id, "alice");
+        assert!(queue.remove(&first).unwrap().is_none());
+        assert_eq!(ids(&queue), vec![second]);
+
+        // Ids are never reused
+        let third = queue
+            .push(user("alice"), conf(), "pool".to_string())
+            .unwrap();
+        assert_ne!(third, first);
+    }
+
+    #[test]
+    fn changes_are_notified() {
+        let queue = AdmissionQueue::new();
+        let mut events = queue.subscribe();
+        let id = queue
+            .push(user("alice"), conf(), "pool".to_string())
+            .unwrap();
+        assert!(events.try_recv().is_ok());
+        queue.remove("unknown").unwrap();
+        assert!(events.try_recv().is_err());
+        queue.remove(&id).unwrap();
+        assert!(events.try_recv().is_ok());
+    }
+}
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::BTreeMap,
@@ -6,17 +7,95 @@ use std::{
 };
 
 #[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Session {
+    pub id: String,
     pub user_id: String,
+    /// Id of the template this session has been created from
+    pub template_id: String,
+    /// Version of the template this session has been created from
+    pub template_version: u64,
     pub template: Template,
     pub url: String,
     pub pod: Pod,
     #[serde(with = "duration")]
     pub duration: Duration,
     pub node: String,
+    /// Pool this session is deployed on, or will be once scheduled
+    pub pool_affinity: String,
+    pub resources: Resources,
+    /// Time left before this session expires, once started
+    #[serde(with = "option_duration")]
+    pub remaining: Option<Duration>,
+    /// Repository cloned in this session, overriding the template one
+    pub repository: Option<Repository>,
+}
+
+/// Time left before `duration` elapses since `start_time`, if started
+pub fn remaining_time(start_time: Option<SystemTime>, duration: Duration) -> Option<Duration> {
+    start_time
+        .and_then(|start_time| start_time.elapsed().ok())
+        .map(|elapsed| duration.checked_sub(elapsed).unwrap_or_default())
+}
+
+/// A change of a `Session`, as streamed to clients
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SessionEvent {
+    Updated {
+        id: String,
+        session: Box<Session>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Deleted {
+        id: String,
+        user_id: String,
+    },
+    /// The session will soon expire
+    #[serde(rename_all = "camelCase")]
+    Expiring {
+        id: String,
+        user_id: String,
+        #[serde(with = "duration")]
+        remaining: Duration,
+    },
+}
+
+impl SessionEvent {
+    /// Id of the user owning the changed session
+    pub fn user_id(&self) -> &str {
+        match self {
+            SessionEvent::Updated { session, .. } => &session.user_id,
+            SessionEvent::Deleted { user_id, .. } | SessionEvent::Expiring { user_id, .. } => {
+                user_id
+            }
+        }
+    }
+}
+
+/// A session creation request waiting for its pool to have capacity
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedSession {
+    pub id: String,
+    pub user_id: String,
+    pub template: String,
+    pub pool_affinity: String,
+    /// 1-based position among requests waiting for the same pool
+    pub position: usize,
+    /// Estimated seconds before admission, if known
+    pub eta: Option<u64>,
+}
+
+/// The outcome of a session creation request
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Admission {
+    Created { id: String },
+    Queued(QueuedSession),
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub enum Phase {
     Pending,
     Running,
@@ -50,7 +129,7 @@ pub struct Pod {
     pub container: Option<ContainerStatus>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub enum ContainerPhase {
     Running,
     Terminated,
@@ -58,7 +137,7 @@ pub enum ContainerPhase {
     Unknown,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub struct ContainerStatus {
     pub phase: ContainerPhase,
     pub reason: Option<String>,
@@ -71,6 +150,52 @@ pub struct Pool {
     pub name: String,
     pub instance_type: Option<String>,
     pub nodes: Vec<Node>,
+    /// Maximum number of sessions this pool can host
+    pub capacity: usize,
+    /// Number of sessions hosted by this pool, including those not scheduled yet
+    pub used: usize,
+    pub available: usize,
+}
+
+impl Pool {
+    pub fn new(
+        name: String,
+        instance_type: Option<String>,
+        nodes: Vec<Node>,
+        max_sessions_per_pod: usize,
+    ) -> Self {
+        let capacity = nodes.len() * max_sessions_per_pod;
+        Pool {
+            name,
+            instance_type,
+            nodes,
+            capacity,
+            used: 0,
+            available: capacity,
+        }
+    }
+
+    /// `true` if `session` runs on one of this pool nodes, or will once scheduled
+    pub fn hosts(&self, session: &Session) -> bool {
+        if session.node.is_empty() {
+            session.pool_affinity == self.name
+        } else {
+            self.nodes.iter().any(|node| node.hostname == session.node)
+        }
+    }
+
+    /// Accounts for sessions among `sessions` hosted by this pool
+    pub fn with_sessions<'a>(self, sessions: impl IntoIterator<Item = &'a Session>) -> Self {
+        let used = sessions
+            .into_iter()
+            .filter(|session| self.hosts(session))
+            .count();
+        Pool {
+            used,
+            available: self.capacity.saturating_sub(used),
+            ..self
+        }
+    }
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -83,10 +208,17 @@ pub struct Node {
 #[serde(rename_all = "camelCase")]
 pub struct SessionConfiguration {
     pub template: String,
+    /// Version of `template` to use, defaults to the latest one
+    pub template_version: Option<u64>,
     #[serde(default)]
     #[serde(with = "option_duration")]
     pub duration: Option<Duration>,
     pub pool_affinity: Option<String>,
+    /// Repository cloned in place of the template one, e.g. a fork
+    pub repository: Option<String>,
+    /// Git ref of the cloned repository, e.g. a branch
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -105,6 +237,137 @@ pub struct SessionDefaults {
     pub max_duration: Duration,
     pub pool_affinity: String,
     pub max_sessions_per_pod: usize,
+    pub max_sessions_per_user: usize,
+    /// Resources of session containers, unless set by their template
+    pub resources: Resources,
+    /// Upper bounds of resources templates can set
+    pub max_resources: ResourceQuantities,
+    /// Sessions with no activity for that long are undeployed, unless set by their template
+    #[serde(with = "option_duration")]
+    pub idle_timeout: Option<Duration>,
+    /// Sessions are warned that long before expiring
+    #[serde(with = "duration")]
+    pub expiry_warning: Duration,
+}
+
+/// Persistent workspaces configuration. Workspaces are disabled when not configured.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceDefaults {
+    /// Size of each workspace, as a Kubernetes quantity
+    pub size: String,
+    /// Path workspaces are mounted at in session containers
+    pub path: String,
+    pub storage_class: Option<String>,
+    /// Workspaces unused for that many days are deleted
+    pub retention_days: Option<u64>,
+}
+
+/// Persistent storage of a user, mounted by all its sessions
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Workspace {
+    pub user_id: String,
+    pub size: String,
+    pub phase: String,
+    /// Last time a session mounting this workspace was started
+    #[serde(with = "system_time")]
+    pub last_used: Option<SystemTime>,
+}
+
+/// In-cluster template image builds configuration. Builds are disabled when not configured.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildDefaults {
+    /// Registry images are pushed to, e.g. `docker.io/paritytech`
+    pub registry: String,
+    /// Secret holding the registry credentials, as a `.dockerconfigjson`
+    pub push_secret: Option<String>,
+    /// Playground repository, providing template Dockerfiles
+    pub playground: Repository,
+}
+
+/// An image build of a template, updating its image on success
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Build {
+    pub id: String,
+    /// Id of the built template
+    pub template: String,
+    pub repository: Repository,
+    /// Image pushed on success
+    pub image: String,
+    pub phase: Phase,
+    pub message: String,
+    #[serde(with = "system_time")]
+    pub start_time: Option<SystemTime>,
+    #[serde(with = "system_time")]
+    pub completion_time: Option<SystemTime>,
+}
+
+/// Defaults to the template repository and ref
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct BuildConfiguration {
+    pub repository: Option<String>,
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RollbackConfiguration {
+    /// Version the template is rolled back to
+    pub version: u64,
+}
+
+///
+/// Limits on sessions a user can create. Unset fields are not limited.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    /// Maximum number of concurrent sessions
+    pub max_sessions: Option<usize>,
+    /// Maximum number of session minutes that can be booked per day, summing all sessions durations
+    pub max_minutes_per_day: Option<u64>,
+    /// Ids of templates sessions can be created from
+    pub templates: Option<Vec<String>>,
+}
+
+impl Quota {
+    ///
+    /// Combines `quotas` into the most permissive one: a limit only applies if set by all of them.
+    ///
+    pub fn most_permissive(quotas: &[&Quota]) -> Quota {
+        fn combine<T>(values: Vec<Option<T>>, f: impl Fn(T, T) -> T) -> Option<T> {
+            let mut values = values.into_iter().collect::<Option<Vec<T>>>()?.into_iter();
+            let first = values.next()?;
+            Some(values.fold(first, f))
+        }
+
+        Quota {
+            max_sessions: combine(quotas.iter().map(|q| q.max_sessions).collect(), usize::max),
+            max_minutes_per_day: combine(
+                quotas.iter().map(|q| q.max_minutes_per_day).collect(),
+                u64::max,
+            ),
+            templates: combine(
+                quotas.iter().map(|q| q.templates.clone()).collect(),
+                |mut all, templates| {
+                    all.extend(templates);
+                    all
+                },
+            ),
+        }
+    }
+
+    /// Completes limits unset in `self` with those of `other`
+    pub fn or(self, other: Quota) -> Quota {
+        Quota {
+            max_sessions: self.max_sessions.or(other.max_sessions),
+            max_minutes_per_day: self.max_minutes_per_day.or(other.max_minutes_per_day),
+            templates: self.templates.or(other.templates),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -116,6 +379,8 @@ pub struct User {
     #[serde(default = "default_as_false")]
     pub can_customize_pool_affinity: bool,
     pub pool_affinity: Option<String>,
+    #[serde(flatten)]
+    pub quota: Quota,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -127,6 +392,8 @@ pub struct UserConfiguration {
     #[serde(default = "default_as_false")]
     pub can_customize_pool_affinity: bool,
     pub pool_affinity: Option<String>,
+    #[serde(flatten)]
+    pub quota: Quota,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -138,7 +405,33 @@ pub struct UserUpdateConfiguration {
     #[serde(default = "default_as_false")]
     pub can_customize_pool_affinity: bool,
     pub pool_affinity: Option<String>,
+    #[serde(flatten)]
+    pub quota: Quota,
+}
+impl From<UserConfiguration> for User {
+    fn from(conf: UserConfiguration) -> Self {
+        User {
+            admin: conf.admin,
+            can_customize_duration: conf.can_customize_duration,
+            can_customize_pool_affinity: conf.can_customize_pool_affinity,
+            pool_affinity: conf.pool_affinity,
+            quota: conf.quota,
+        }
+    }
+}
+
+impl From<UserUpdateConfiguration> for User {
+    fn from(conf: UserUpdateConfiguration) -> Self {
+        User {
+            admin: conf.admin,
+            can_customize_duration: conf.can_customize_duration,
+            can_customize_pool_affinity: conf.can_customize_pool_affinity,
+            pool_affinity: conf.pool_affinity,
+            quota: conf.quota,
+        }
+    }
 }
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct LoggedUser {
     pub id: String,
@@ -147,6 +440,8 @@ pub struct LoggedUser {
     pub pool_affinity: Option<String>,
     pub can_customize_duration: bool,
     pub can_customize_pool_affinity: bool,
+    /// Quota defined for this user, not accounting for its organizations
+    pub quota: Quota,
 }
 
 impl LoggedUser {
@@ -170,28 +465,307 @@ impl LoggedUser {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub struct Template {
     pub name: String,
     pub image: String,
     pub description: String,
+    /// Repository the image is built from, as a git URL or a GitHub `owner/name`
+    pub repository: Option<String>,
+    /// Git ref of `repository` the image is built from
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
     pub tags: Option<BTreeMap<String, String>>,
     pub runtime: Option<RuntimeConfiguration>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl Template {
+    /// Tag making a template visible to everyone, when `true`
+    pub const PUBLIC_TAG: &'static str = "public";
+    /// Tag restricting a template to some users, as comma separated ids
+    pub const USERS_TAG: &'static str = "users";
+    /// Tag restricting a template to members of some GitHub organizations, as comma separated ids
+    pub const ORGANIZATIONS_TAG: &'static str = "organizations";
+
+    ///
+    /// `true` if `user` can see and use this `Template`. Anonymous users are represented by `None`.
+    ///
+    /// Public templates are visible to everyone. Templates restricted to some users or organizations
+    /// are only visible to them and to `admin`s, read rights are not enough. Others are visible to all logged users.
+    ///
+    pub fn is_visible_to(&self, user: Option<&LoggedUser>) -> bool {
+        let tag = |name: &str| self.tags.as_ref().and_then(|tags| tags.get(name));
+        let contains = |ids: &String, id: &str| {
+            ids.split(',')
+                .any(|candidate| candidate.trim().eq_ignore_ascii_case(id))
+        };
+        if matches!(tag(Template::PUBLIC_TAG), Some(public) if public == "true") {
+            return true;
+        }
+        let user = match user {
+            Some(user) => user,
+            None => return false,
+        };
+        match (tag(Template::USERS_TAG), tag(Template::ORGANIZATIONS_TAG)) {
+            (None, None) => true,
+            (users, organizations) => {
+                user.admin
+                    || matches!(users, Some(users) if contains(users, &user.id))
+                    || matches!(organizations, Some(organizations) if user
+                        .organizations
+                        .iter()
+                        .any(|organization| contains(organizations, organization)))
+            }
+        }
+    }
+
+    /// Returns a list of problems preventing this `Template` to be used, if any
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.image.is_empty() {
+            problems.push("image is empty".to_string());
+        } else if self.image.contains(char::is_whitespace) {
+            problems.push(format!("image '{}' contains whitespaces", self.image));
+        }
+        if let Some(repository) = &self.repository {
+            problems.extend(
+                Repository {
+                    url: repository.clone(),
+                    reference: self.reference.clone(),
+                }
+                .validate(),
+            );
+        }
+        if let Some(runtime) = &self.runtime {
+            for env in runtime.env.iter().flatten() {
+                if env.name.is_empty() {
+                    problems.push("env variable with empty name".to_string());
+                }
+            }
+            let mut names = Vec::new();
+            for port in runtime.ports.iter().flatten() {
+                if names.contains(&&port.name) {
+                    problems.push(format!("duplicated port name '{}'", port.name));
+                }
+                names.push(&port.name);
+                if port.port < 1 || port.port > 65535 {
+                    problems.push(format!("port '{}' is out of range", port.name));
+                }
+                if !port.path.starts_with('/') {
+                    problems.push(format!("path of port '{}' must start with /", port.name));
+                }
+            }
+            if let Some(resources) = &runtime.resources {
+                // Maximums are checked when creating sessions
+                problems.extend(resources.validate(&ResourceQuantities::default()));
+            }
+        }
+        problems
+    }
+}
+
+/// A version of a `Template`, as kept in its history
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct TemplateVersion {
+    /// Increases with each update of the template
+    pub version: u64,
+    pub template: Template,
+}
+
+/// A git repository, checked out at `reference`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Repository {
+    /// A git URL, or a GitHub `owner/name`
+    pub url: String,
+    /// Defaults to the repository default branch
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+}
+
+impl Repository {
+    /// The URL this repository can be cloned from
+    pub fn clone_url(&self) -> String {
+        if self.url.contains(':') {
+            self.url.clone()
+        } else {
+            format!("https://github.com/{}.git", self.url)
+        }
+    }
+
+    /// Returns a list of problems preventing this `Repository` to be cloned, if any
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        // Values are passed as git arguments, they must not be mistaken for options
+        if self.url.is_empty() || self.url.starts_with('-') {
+            problems.push(format!("invalid repository '{}'", self.url));
+        } else if self.url.contains(char::is_whitespace) {
+            problems.push(format!("repository '{}' contains whitespaces", self.url));
+        }
+        if let Some(reference) = &self.reference {
+            if reference.is_empty() || reference.starts_with('-') {
+                problems.push(format!("invalid ref '{}'", reference));
+            } else if reference.contains(char::is_whitespace) {
+                problems.push(format!("ref '{}' contains whitespaces", reference));
+            }
+        }
+        problems
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct RuntimeConfiguration {
     pub env: Option<Vec<NameValuePair>>,
     pub ports: Option<Vec<Port>>,
+    /// Number of idle pods kept started, ready to be claimed by new sessions
+    pub warm_pool_size: Option<usize>,
+    pub resources: Option<Resources>,
+    /// Minutes of inactivity after which sessions are undeployed
+    pub idle_timeout: Option<u64>,
+    /// Run in session containers before they are stopped
+    pub pre_stop: Option<Command>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+// Parses a Kubernetes quantity (e.g. `500m`, `1.5`, `2Gi` or `1e3`) into its value
+fn parse_quantity(quantity: &str) -> Option<f64> {
+    const SUFFIXES: [(&str, f64); 13] = [
+        ("Ki", 1024.0),
+        ("Mi", 1_048_576.0),
+        ("Gi", 1_073_741_824.0),
+        ("Ti", 1_099_511_627_776.0),
+        ("Pi", 1_125_899_906_842_624.0),
+        ("Ei", 1_152_921_504_606_846_976.0),
+        ("m", 1e-3),
+        ("k", 1e3),
+        ("M", 1e6),
+        ("G", 1e9),
+        ("T", 1e12),
+        ("P", 1e15),
+        ("E", 1e18),
+    ];
+    let (number, multiplier) = SUFFIXES
+        .iter()
+        .find_map(|(suffix, multiplier)| {
+            quantity
+                .strip_suffix(suffix)
+                .map(|number| (number, *multiplier))
+        })
+        .unwrap_or((quantity, 1.0));
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite() && *value >= 0.0)
+        .map(|value| value * multiplier)
+}
+
+///
+/// Amounts of compute resources, as Kubernetes quantities (e.g. `500m` of CPU or `2Gi` of memory).
+///
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceQuantities {
+    pub cpu: Option<String>,
+    pub memory: Option<String>,
+    pub ephemeral_storage: Option<String>,
+}
+
+impl ResourceQuantities {
+    /// Completes quantities unset in `self` with those of `other`
+    pub fn or(self, other: ResourceQuantities) -> ResourceQuantities {
+        ResourceQuantities {
+            cpu: self.cpu.or(other.cpu),
+            memory: self.memory.or(other.memory),
+            ephemeral_storage: self.ephemeral_storage.or(other.ephemeral_storage),
+        }
+    }
+
+    /// Set quantities, keyed by Kubernetes resource name
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        vec![
+            ("cpu", &self.cpu),
+            ("memory", &self.memory),
+            ("ephemeral-storage", &self.ephemeral_storage),
+        ]
+        .into_iter()
+        .filter_map(|(name, quantity)| {
+            quantity
+                .clone()
+                .map(|quantity| (name.to_string(), quantity))
+        })
+        .collect()
+    }
+}
+
+/// Resources requested by a session container, and the limits it can't exceed
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct Resources {
+    #[serde(default)]
+    pub requests: ResourceQuantities,
+    #[serde(default)]
+    pub limits: ResourceQuantities,
+}
+
+impl Resources {
+    /// Completes quantities unset in `self` with those of `other`
+    pub fn or(self, other: Resources) -> Resources {
+        Resources {
+            requests: self.requests.or(other.requests),
+            limits: self.limits.or(other.limits),
+        }
+    }
+
+    /// Returns a list of problems with these `Resources`, including quantities exceeding `max`
+    pub fn validate(&self, max: &ResourceQuantities) -> Vec<String> {
+        // Returns `bound` if `value` exceeds it. Invalid bounds are ignored.
+        fn exceeds(value: f64, bound: Option<&String>) -> Option<&String> {
+            bound.filter(|bound| matches!(parse_quantity(bound), Some(bound) if value > bound))
+        }
+
+        let mut problems = Vec::new();
+        let max = max.to_map();
+        let limits = self.limits.to_map();
+        for (kind, quantities) in &[
+            ("request", self.requests.to_map()),
+            ("limit", limits.clone()),
+        ] {
+            for (name, quantity) in quantities {
+                let value = match parse_quantity(quantity) {
+                    Some(value) => value,
+                    None => {
+                        problems.push(format!(
+                            "{} {} '{}' is not a valid quantity",
+                            name, kind, quantity
+                        ));
+                        continue;
+                    }
+                };
+                if let Some(max) = exceeds(value, max.get(name)) {
+                    problems.push(format!(
+                        "{} {} {} exceeds the maximum of {}",
+                        name, kind, quantity, max
+                    ));
+                }
+                if *kind == "request" {
+                    if let Some(limit) = exceeds(value, limits.get(name)) {
+                        problems.push(format!(
+                            "{} request {} exceeds its limit of {}",
+                            name, quantity, limit
+                        ));
+                    }
+                }
+            }
+        }
+        problems
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub struct NameValuePair {
     pub name: String,
     pub value: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub struct Port {
     pub name: String,
     pub protocol: Option<String>,
@@ -200,7 +774,7 @@ pub struct Port {
     pub target: Option<i32>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Command {
     pub name: String,
@@ -208,7 +782,7 @@ pub struct Command {
     pub working_directory: String,
 }
 
-/// Utils
+// Utils
 
 mod system_time {
     use serde::{self, Serializer};
@@ -226,9 +800,19 @@ mod system_time {
 }
 
 mod option_duration {
-    use serde::{self, Deserialize, Deserializer};
+    use serde::{self, Deserialize, Deserializer, Serializer};
     use std::time::Duration;
 
+    pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match duration {
+            Some(duration) => serializer.serialize_some(&(duration.as_secs() / 60)),
+            None => serializer.serialize_none(),
+        }
+    }
+
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
     where
         D: Deserializer<'de>,
@@ -254,3 +838,135 @@ mod duration {
 fn default_as_false() -> bool {
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: &str, admin: bool, organizations: &[&str]) -> LoggedUser {
+        LoggedUser {
+            id: id.to_string(),
+            admin,
+            organizations: organizations.iter().map(|o| o.to_string()).collect(),
+            pool_affinity: None,
+            can_customize_duration: false,
+            can_customize_pool_affinity: false,
+            quota: Quota::default(),
+        }
+    }
+
+    fn template(tags: &[(&str, &str)]) -> Template {
+        Template {
+            name: "name".to_string(),
+            image: "image".to_string(),
+            description: "description".to_string(),
+            repository: None,
+            reference: None,
+            tags: Some(
+                tags.iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            runtime: None,
+        }
+    }
+
+    fn quantities(cpu: Option<&str>, memory: Option<&str>) -> ResourceQuantities {
+        ResourceQuantities {
+            cpu: cpu.map(String::from),
+            memory: memory.map(String::from),
+            ephemeral_storage: None,
+        }
+    }
+
+    #[test]
+    fn most_permissive_quota() {
+        let limited = Quota {
+            max_sessions: Some(2),
+            max_minutes_per_day: Some(60),
+            templates: Some(vec!["a".to_string()]),
+        };
+        let other = Quota {
+            max_sessions: Some(5),
+            max_minutes_per_day: None,
+            templates: Some(vec!["b".to_string()]),
+        };
+        assert_eq!(
+            Quota::most_permissive(&[&limited, &other]),
+            Quota {
+                max_sessions: Some(5),
+                max_minutes_per_day: None,
+                templates: Some(vec!["a".to_string(), "b".to_string()]),
+            }
+        );
+        assert_eq!(Quota::most_permissive(&[&limited]), limited);
+        assert_eq!(Quota::most_permissive(&[]), Quota::default());
+    }
+
+    #[test]
+    fn parse_quantities() {
+        assert_eq!(parse_quantity("2"), Some(2.0));
+        assert_eq!(parse_quantity("1.5"), Some(1.5));
+        assert_eq!(parse_quantity("500m"), Some(0.5));
+        assert_eq!(parse_quantity("2Gi"), Some(2.0 * 1_073_741_824.0));
+        assert_eq!(parse_quantity("3M"), Some(3e6));
+        assert_eq!(parse_quantity("1e3"), Some(1e3));
+        assert_eq!(parse_quantity(""), None);
+        assert_eq!(parse_quantity("-1"), None);
+        assert_eq!(parse_quantity("Gi"), None);
+        assert_eq!(parse_quantity("1Xi"), None);
+    }
+
+    #[test]
+    fn validate_resources() {
+        let max = quantities(Some("2"), Some("4Gi"));
+        let valid = Resources {
+            requests: quantities(Some("500m"), Some("1Gi")),
+            limits: quantities(Some("2"), Some("2Gi")),
+        };
+        assert!(valid.validate(&max).is_empty());
+
+        let invalid = Resources {
+            requests: quantities(Some("1"), Some("lots")),
+            limits: quantities(Some("500m"), Some("8Gi")),
+        };
+        assert_eq!(
+            invalid.validate(&max),
+            vec![
+                "cpu request 1 exceeds its limit of 500m",
+                "memory request 'lots' is not a valid quantity",
+                "memory limit 8Gi exceeds the maximum of 4Gi",
+            ]
+        );
+
+        // Invalid maximums are ignored
+        assert!(valid.validate(&quantities(Some("many"), None)).is_empty());
+    }
+
+    #[test]
+    fn template_visibility() {
+        let alice = user("alice", false, &["org"]);
+        let bob = user("Bob", false, &["paritytech"]);
+        let admin = user("admin", true, &[]);
+
+        let public = template(&[(Template::PUBLIC_TAG, "true"), (Template::USERS_TAG, "x")]);
+        assert!(public.is_visible_to(None));
+        assert!(public.is_visible_to(Some(&alice)));
+
+        let unrestricted = template(&[]);
+        assert!(!unrestricted.is_visible_to(None));
+        assert!(unrestricted.is_visible_to(Some(&alice)));
+
+        let users = template(&[(Template::USERS_TAG, "carol, bob")]);
+        assert!(users.is_visible_to(Some(&bob)));
+        assert!(!users.is_visible_to(Some(&alice)));
+        assert!(users.is_visible_to(Some(&admin)));
+        assert!(!users.is_visible_to(None));
+
+        let organizations = template(&[(Template::ORGANIZATIONS_TAG, "org")]);
+        assert!(organizations.is_visible_to(Some(&alice)));
+        // Read rights are not enough
+        assert!(!organizations.is_visible_to(Some(&bob)));
+        assert!(organizations.is_visible_to(Some(&admin)));
+    }
+}
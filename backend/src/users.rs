@@ -0,0 +1,251 @@
+//! Persistence of `User`s
+use crate::{
+    error::{Error, Result},
+    types::User,
+};
+use async_trait::async_trait;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::task;
+
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    async fn get(&self, id: &str) -> Result<Option<User>>;
+
+    async fn list(&self) -> Result<BTreeMap<String, User>>;
+
+    /// Creates or replaces the `User` identified by `id`
+    async fn put(&self, id: &str, user: User) -> Result<()>;
+
+    /// Err if provided `id` doesn't exist
+    async fn delete(&self, id: &str) -> Result<()>;
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+enum Entry {
+    Put { id: String, user: User },
+    Delete { id: String },
+}
+
+///
+/// A `UserStore` backed by an append-only file of JSON entries, one per line.
+///
+/// Each change is appended so that the full history is kept; current state is rebuilt by replaying all entries.
+/// A final entry torn by an interrupted write is dropped when opening.
+///
+pub struct FileUserStore {
+    path: PathBuf,
+    users: Arc<Mutex<BTreeMap<String, User>>>,
+}
+
+impl FileUserStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut users = BTreeMap::new();
+        if path.exists() {
+            let content = fs::read(&path).map_err(|err| Error::Failure(err.into()))?;
+            let mut offset = 0;
+            let mut lines = content.split(|b| *b == b'\n').peekable();
+            while let Some(line) = lines.next() {
+                let start = offset;
+                offset += line.len() + 1;
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                let entry = match serde_json::from_slice(line) {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        let last = lines.all(|line| line.iter().all(u8::is_ascii_whitespace));
+                        if !last {
+                            return Err(Error::Failure(err.into()));
+                        }
+                        warn!("Dropping torn last entry of {}: {}", path.display(), err);
+                        OpenOptions::new()
+                            .write(true)
+                            .open(&path)
+                            .and_then(|file| file.set_len(start as u64))
+                            .map_err(|err| Error::Failure(err.into()))?;
+                        break;
+                    }
+                };
+                match entry {
+                    Entry::Put { id, user } => {
+                        users.insert(id, user);
+                    }
+                    Entry::Delete { id } => {
+                        users.remove(&id);
+                    }
+                }
+            }
+        }
+        Ok(FileUserStore {
+            path,
+            users: Arc::new(Mutex::new(users)),
+        })
+    }
+
+    //
+    // Appends `entry` then applies it to `users`, in a blocking task as the file is synced to disk.
+    // Fails with `NotFound` when deleting an unknown user.
+    //
+    async fn apply(&self, entry: Entry) -> Result<()> {
+        let path = self.path.clone();
+        let users = self.users.clone();
+        task::spawn_blocking(move || {
+            let mut users = users
+                .lock()
+                .map_err(|_| Error::Failure("Failed to acquire users lock".into()))?;
+            if let Entry::Delete { id } = &entry {
+                if !users.contains_key(id) {
+                    return Err(Error::NotFound("user"));
+                }
+            }
+            append(&path, &entry)?;
+            match entry {
+                Entry::Put { id, user } => {
+                    users.insert(id, user);
+                }
+                Entry::Delete { id } => {
+                    users.remove(&id);
+                }
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|err| Error::Failure(err.into()))?
+    }
+}
+
+fn append(path: &Path, entry: &Entry) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| Error::Failure(err.into()))?;
+    let line = serde_json::to_string(entry).map_err(|err| Error::Failure(err.into()))?;
+    writeln!(file, "{}", line).map_err(|err| Error::Failure(err.into()))?;
+    file.sync_data().map_err(|err| Error::Failure(err.into()))
+}
+
+#[async_trait]
+impl UserStore for FileUserStore {
+    async fn get(&self, id: &str) -> Result<Option<User>> {
+        Ok(self.list().await?.get(id).cloned())
+    }
+
+    async fn list(&self) -> Result<BTreeMap<String, User>> {
+        self.users
+            .lock()
+            .map(|users| users.clone())
+            .map_err(|_| Error::Failure("Failed to acquire users lock".into()))
+    }
+
+    async fn put(&self, id: &str, user: User) -> Result<()> {
+        self.apply(Entry::Put {
+            id: id.to_string(),
+            user,
+        })
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.apply(Entry::Delete { id: id.to_string() }).await
+    }
+}
+
+/// Copies all `User`s from `from` into `to`. Returns the number of imported users.
+pub async fn migrate(from: &dyn UserStore, to: &dyn UserStore) -> Result<usize> {
+    let users = from.list().await?;
+    for (id, user) in &users {
+        to.put(id, user.clone()).await?;
+    }
+    Ok(users.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Quota;
+    use std::{env, fs};
+
+    fn user(admin: bool) -> User {
+        User {
+            admin,
+            can_customize_duration: false,
+            can_customize_pool_affinity: false,
+            pool_affinity: None,
+            quota: Quota::default(),
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            env::temp_dir().join(format!("playground-{}-{}.jsonl", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn file_store_replays_entries() {
+        let path = temp_path("users-replay");
+        let store = FileUserStore::open(&path).unwrap();
+        store.put("alice", user(false)).await.unwrap();
+        store.put("bob", user(false)).await.unwrap();
+        store.put("alice", user(true)).await.unwrap();
+        store.delete("bob").await.unwrap();
+        assert!(matches!(
+            store.delete("bob").await,
+            Err(Error::NotFound("user"))
+        ));
+
+        let reopened = FileUserStore::open(&path).unwrap();
+        let users = reopened.list().await.unwrap();
+        assert_eq!(users.keys().collect::<Vec<_>>(), vec!["alice"]);
+        assert!(users["alice"].admin);
+        // The full history is kept
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_store_drops_torn_last_entry() {
+        let path = temp_path("users-torn");
+        fs::write(
+            &path,
+            "{\"op\":\"delete\",\"id\":\"alice\"}\n{\"op\":\"put\",\"id\":\"bo",
+        )
+        .unwrap();
+        let store = FileUserStore::open(&path).unwrap();
+        assert!(store.list().await.unwrap().is_empty());
+        store.put("alice", user(false)).await.unwrap();
+
+        let reopened = FileUserStore::open(&path).unwrap();
+        let users = reopened.list().await.unwrap();
+        assert_eq!(users.keys().collect::<Vec<_>>(), vec!["alice"]);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_store_rejects_corrupt_files() {
+        let path = temp_path("users-corrupt");
+        fs::write(
+            &path,
+            "{\"op\":\"delete\",\"id\":\"alice\"}\n\nnot json\n{\"op\":\"delete\",\"id\":\"bob\"}\n",
+        )
+        .unwrap();
+        assert!(matches!(FileUserStore::open(&path), Err(Error::Failure(_))));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 import { fetchWithTimeout, rpc } from './rpc';
-import { Playground, Pool, Session, SessionConfiguration, SessionUpdateConfiguration, User, UserConfiguration, UserUpdateConfiguration, } from './types';
+import { Admission, Build, BuildConfiguration, Playground, Pool, QueuedSession, Session, SessionConfiguration, SessionUpdateConfiguration, Template, TemplateVersion, User, UserConfiguration, UserUpdateConfiguration, Workspace, } from './types';
 
 export class Client {
 
@@ -7,7 +7,11 @@ export class Client {
     static usersResource = 'users';
     static sessionResource = 'session';
     static sessionsResource = 'sessions';
+    static queueResource = 'queue';
     static poolsResource = 'pools';
+    static workspacesResource = 'workspaces';
+    static templatesResource = 'templates';
+    static buildsResource = 'builds';
 
     private readonly base: string;
     private readonly timeout: number;
@@ -35,6 +39,48 @@ export class Client {
         }, this.timeout);
     }
 
+    // Templates
+
+    /* Creates or replaces template `id` */
+    async updateTemplate(id: string, template: Template, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.templatesResource, id), {
+            method: 'PUT',
+            body: JSON.stringify(template),
+            ...init
+        }, this.timeout);
+    }
+
+    /* Updates fields of template `id` set in `patch` */
+    async patchTemplate(id: string, patch: Partial<Template>, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.templatesResource, id), {
+            method: 'PATCH',
+            body: JSON.stringify(patch),
+            ...init
+        }, this.timeout);
+    }
+
+    /* Fails if some sessions were created from template `id`, unless `force`d */
+    async deleteTemplate(id: string, force = false, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(`${this.path(Client.templatesResource, id)}${force ? '?force=true' : ''}`, {
+            method: 'DELETE',
+            ...init
+        }, this.timeout);
+    }
+
+    /* Versions kept of template `id`, oldest first */
+    async listTemplateVersions(id: string, init: RequestInit = this.defaultInit): Promise<TemplateVersion[]> {
+        return rpc(this.path(Client.templatesResource, id, 'versions'), init, this.timeout);
+    }
+
+    /* Restores `version` of template `id`, as a new version */
+    async rollbackTemplate(id: string, version: number, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.templatesResource, id, 'rollback'), {
+            method: 'POST',
+            body: JSON.stringify({ version }),
+            ...init
+        }, this.timeout);
+    }
+
     // Users
 
     async getUser(id: string, init: RequestInit = this.defaultInit): Promise<User | null> {
@@ -68,30 +114,27 @@ export class Client {
         }, this.timeout);
     }
 
-    // Current Session
+    // Sessions of the current user
 
-    async getCurrentSession(init: RequestInit = this.defaultInit): Promise<Session | null> {
+    async listCurrentSessions(init: RequestInit = this.defaultInit): Promise<Session[]> {
         return rpc(this.path(Client.sessionResource), init, this.timeout);
     }
 
-    async createCurrentSession(conf: SessionConfiguration, init: RequestInit = this.defaultInit): Promise<void> {
+    /* Returns the id of the new session, or its position in the queue if at capacity */
+    async createCurrentSession(conf: SessionConfiguration, init: RequestInit = this.defaultInit): Promise<Admission> {
         return rpc(this.path(Client.sessionResource), {
-            method: 'PUT',
+            method: 'POST',
             body: JSON.stringify(conf),
             ...init
         }, this.timeout);
     }
 
-    async updateCurrentSession(conf: SessionUpdateConfiguration, init: RequestInit = this.defaultInit): Promise<void> {
-        return rpc(this.path(Client.sessionResource), {
-            method: 'PATCH',
-            body: JSON.stringify(conf),
-            ...init
-        }, this.timeout);
+    async listQueuedSessions(init: RequestInit = this.defaultInit): Promise<QueuedSession[]> {
+        return rpc(this.path(Client.sessionResource, Client.queueResource), init, this.timeout);
     }
 
-    async deleteCurrentSession(init: RequestInit = this.defaultInit): Promise<void> {
-        return rpc(this.path(Client.sessionResource), {
+    async cancelQueuedSession(id: string, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.sessionResource, Client.queueResource, id), {
             method: 'DELETE',
             ...init
         }, this.timeout);
@@ -99,22 +142,26 @@ export class Client {
 
     // Sessions
 
+    async getSession(id: string, init: RequestInit = this.defaultInit): Promise<Session | null> {
+        return rpc(this.path(Client.sessionsResource, id), init, this.timeout);
+    }
+
     async listSessions(init: RequestInit = this.defaultInit): Promise<Record<string, Session>> {
         return rpc(this.path(Client.sessionsResource), init, this.timeout);
     }
 
-    async createSession(id: string, conf: SessionConfiguration, init: RequestInit = this.defaultInit): Promise<void> {
+    async updateSession(id: string, conf: SessionUpdateConfiguration, init: RequestInit = this.defaultInit): Promise<void> {
         return rpc(this.path(Client.sessionsResource, id), {
-            method: 'PUT',
+            method: 'PATCH',
             body: JSON.stringify(conf),
             ...init
         }, this.timeout);
     }
 
-    async updateSession(id: string, conf: SessionUpdateConfiguration, init: RequestInit = this.defaultInit): Promise<void> {
-        return rpc(this.path(Client.sessionsResource, id), {
-            method: 'PATCH',
-            body: JSON.stringify(conf),
+    /* Reports some activity of session `id`, so that it is not undeployed as idle */
+    async heartbeatSession(id: string, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.sessionsResource, id, 'heartbeat'), {
+            method: 'POST',
             ...init
         }, this.timeout);
     }
@@ -136,6 +183,47 @@ export class Client {
         return rpc(this.path(Client.poolsResource), init, this.timeout);
     }
 
+    // Workspaces
+
+    async getWorkspace(userId: string, init: RequestInit = this.defaultInit): Promise<Workspace | null> {
+        return rpc(this.path(Client.workspacesResource, userId), init, this.timeout);
+    }
+
+    async listWorkspaces(init: RequestInit = this.defaultInit): Promise<Record<string, Workspace>> {
+        return rpc(this.path(Client.workspacesResource), init, this.timeout);
+    }
+
+    /* URL downloading a gzipped tarball of the workspace of `userId` */
+    workspaceSnapshotUrl(userId: string): string {
+        return this.path(Client.workspacesResource, userId, 'snapshot');
+    }
+
+    async deleteWorkspace(userId: string, init: RequestInit = this.defaultInit): Promise<void> {
+        return rpc(this.path(Client.workspacesResource, userId), {
+            method: 'DELETE',
+            ...init
+        }, this.timeout);
+    }
+
+    // Builds
+
+    async getBuild(id: string, init: RequestInit = this.defaultInit): Promise<Build | null> {
+        return rpc(this.path(Client.buildsResource, id), init, this.timeout);
+    }
+
+    async listBuilds(init: RequestInit = this.defaultInit): Promise<Record<string, Build>> {
+        return rpc(this.path(Client.buildsResource), init, this.timeout);
+    }
+
+    /* Starts building a new image of template `templateId`. Returns the build id. */
+    async createBuild(templateId: string, conf: BuildConfiguration = {}, init: RequestInit = this.defaultInit): Promise<string> {
+        return rpc(this.path(Client.templatesResource, templateId, Client.buildsResource), {
+            method: 'POST',
+            body: JSON.stringify(conf),
+            ...init
+        }, this.timeout);
+    }
+
     // Login
 
     async login(bearer: string, init: RequestInit = this.defaultInit): Promise<Response> {
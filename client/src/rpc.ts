@@ -42,6 +42,11 @@ async function call<T>(input: RequestInfo, init: RequestInit, timeout: number):
                 return Promise.reject({code: RpcErrorCode.PARSE_ERROR, message: response.statusText});
             }
         } else {
+            // Errors are reported as `{error: {code, message}}` when possible
+            const error = await response.json().then(body => body.error, () => undefined);
+            if (error) {
+                return Promise.reject(error);
+            }
             if (response.status == 401) {
                 return Promise.reject({code: RpcErrorCode.INVALID_REQUEST, message: "User unauthorized"});
             }
@@ -14,6 +14,15 @@ export interface Environment {
 export interface Configuration {
     githubClientId: string,
     session: SessionDefaults,
+    /* Set if workspaces are enabled */
+    workspace?: WorkspaceDefaults,
+}
+
+export interface WorkspaceDefaults {
+    size: string,
+    path: string,
+    storageClass?: string,
+    retentionDays?: number,
 }
 
 export interface SessionDefaults {
@@ -22,6 +31,13 @@ export interface SessionDefaults {
     maxDuration: number,
     poolAffinity: string,
     maxSessionsPerPod: string,
+    maxSessionsPerUser: number,
+    resources: Resources,
+    maxResources: ResourceQuantities,
+    /* Minutes of inactivity after which sessions are undeployed, if set */
+    idleTimeout?: number,
+    /* Sessions are warned that many minutes before expiring */
+    expiryWarning: number,
 }
 
 export interface LoggedUser {
@@ -31,6 +47,9 @@ export interface LoggedUser {
     poolAffinity: string,
     canCustomizeDuration: boolean,
     canCustomizePoolAffinity: boolean,
+    maxSessions?: number,
+    maxMinutesPerDay?: number,
+    templates?: string[],
 }
 
 export interface User {
@@ -38,6 +57,9 @@ export interface User {
     poolAffinity: string,
     canCustomizeDuration: boolean,
     canCustomizePoolAffinity: boolean,
+    maxSessions?: number,
+    maxMinutesPerDay?: number,
+    templates?: string[],
 }
 
 export interface UserConfiguration {
@@ -45,6 +67,9 @@ export interface UserConfiguration {
     poolAffinity?: string,
     canCustomizeDuration: boolean,
     canCustomizePoolAffinity: boolean,
+    maxSessions?: number,
+    maxMinutesPerDay?: number,
+    templates?: string[],
 }
 
 export interface UserUpdateConfiguration {
@@ -52,10 +77,17 @@ export interface UserUpdateConfiguration {
     poolAffinity?: string,
     canCustomizeDuration: boolean,
     canCustomizePoolAffinity: boolean,
+    maxSessions?: number,
+    maxMinutesPerDay?: number,
+    templates?: string[],
 }
 
 export interface Session {
+    id: string,
     userId: string,
+    templateId: string,
+    /* Version of the template this session runs */
+    templateVersion: number,
     url: string,
     template: Template,
     pod: Pod,
@@ -63,12 +95,67 @@ export interface Session {
     duration: number,
     maxDuration: number,
     node: string,
+    poolAffinity: string,
+    resources: Resources,
+    /* The number of minutes left before this session expires, once started */
+    remaining?: number,
+    /* Repository cloned in this session, overriding the template one */
+    repository?: Repository,
+}
+
+export interface Repository {
+    url: string,
+    ref?: string,
+}
+
+export interface QueuedSession {
+    id: string,
+    userId: string,
+    template: string,
+    poolAffinity: string,
+    /* 1-based position among requests waiting for the same pool */
+    position: number,
+    /* Estimated seconds before admission */
+    eta?: number,
+}
+
+export type Admission =
+    | { type: 'created', id: string }
+    | { type: 'queued' } & QueuedSession;
+
+export interface Workspace {
+    userId: string,
+    size: string,
+    phase: string,
+    lastUsed?: number,
+}
+
+/* An image build of a template, updating its image on success */
+export interface Build {
+    id: string,
+    template: string,
+    repository: Repository,
+    /* Image pushed on success */
+    image: string,
+    phase: Phase,
+    message: string,
+    startTime?: number,
+    completionTime?: number,
+}
+
+/* Defaults to the template repository and ref */
+export interface BuildConfiguration {
+    repository?: string,
+    ref?: string,
 }
 
 export interface Pool {
     name: string,
     instanceType?: string,
     nodes: Node[],
+    capacity: number,
+    used: number,
+    available: number,
 }
 
 export interface Node {
@@ -77,9 +164,15 @@ export interface Node {
 
 export interface SessionConfiguration {
     template: string,
+    /* Version of `template` to use, defaults to the latest one */
+    templateVersion?: number,
     /* The number of minutes this session will be able to last */
     duration?: number,
     poolAffinity?: string,
+    /* Repository cloned in place of the template one, as a git URL or a GitHub `owner/name` */
+    repository?: string,
+    /* Git ref of the cloned repository, e.g. a branch */
+    ref?: string,
 }
 
 export interface SessionUpdateConfiguration {
@@ -103,16 +196,49 @@ export interface Port {
 export interface RuntimeConfiguration {
     env?: NameValuePair[],
     ports?: Port[],
+    /* Number of idle pods kept started, ready to be claimed by new sessions */
+    warmPoolSize?: number,
+    resources?: Resources,
+    /* Minutes of inactivity after which sessions are undeployed */
+    idleTimeout?: number,
+    /* Run before session containers are stopped */
+    preStop?: Command,
+}
+
+export interface Command {
+    name: string,
+    run: string,
+    workingDirectory: string,
+}
+
+/* Kubernetes quantities, e.g. `500m` of CPU or `2Gi` of memory */
+export interface ResourceQuantities {
+    cpu?: string,
+    memory?: string,
+    ephemeralStorage?: string,
+}
+
+export interface Resources {
+    requests?: ResourceQuantities,
+    limits?: ResourceQuantities,
 }
 
 export interface Template {
     name: string,
     image: string,
     description: string,
+    /* Repository the image is built from */
+    repository?: string,
+    ref?: string,
     tags?: Record<string, string>,
     runtime?: RuntimeConfiguration,
 }
 
+export interface TemplateVersion {
+    version: number,
+    template: Template,
+}
+
 export type Phase = 'Pending' | 'Running' | 'Succeeded' | 'Failed' | 'Unknown';
 export interface Pod {
     phase: Phase,
@@ -14,14 +14,14 @@ import { StatsPanel } from './panels/stats';
 import { TermsPanel } from './panels/terms';
 import { TheiaPanel } from './panels/theia';
 import { terms } from "./terms";
-import { formatDuration } from "./utils";
+import { currentSession, formatDuration } from "./utils";
 
 function MainPanel({ client, conf, user, id, templates, restartAction, onConnect, onDeployed }: { client: Client, conf: Configuration, user: LoggedUser, id: PanelId, templates: Record<string, Template>, restartAction: () => void, onConnect: () => void, onDeployed: () => void }): JSX.Element {
     switch(id) {
         case PanelId.Session:
           return <SessionPanel client={client} conf={conf} user={user} templates={templates} onRetry={restartAction}
-                    onStop={async () => {
-                        await client.deleteCurrentSession();
+                    onStop={async (session: Session) => {
+                        await client.deleteSession(session.id);
                     }}
                     onDeployed={async conf => {
                         await client.createCurrentSession(conf);
@@ -65,14 +65,14 @@ function WrappedSessionPanel({ params, conf, client, user, templates, selectPane
     const [session, setSession] = useState<Session | null | undefined>(undefined);
 
     useInterval(async () => {
-        const session = await client.getCurrentSession();
+        const session = await currentSession(client);
         setSession(session);
 
         const duration = session?.duration || 0;
         const maxDuration = conf.session.maxDuration;
-        if (maxDuration - duration < 600) { // 10 minutes
+        if (session && maxDuration - duration < 600) { // 10 minutes
             const newDuration = Math.max(maxDuration, duration + 60*30);
-            await client.updateCurrentSession({duration: newDuration}); // Increase session duration
+            await client.updateSession(session.id, {duration: newDuration}); // Increase session duration
         }
     }, 5000);
 
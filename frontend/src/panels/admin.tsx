@@ -92,11 +92,13 @@ export function canCustomize(user: LoggedUser): boolean {
     return canCustomizeDuration(user) || canCustomizePoolAffinity(user);
 }
 
-export function SessionCreationDialog({ client, conf, sessions, user, template, templates, show, onCreate, onHide, allowUserSelection = false }: { client: Client, conf: Configuration, sessions?: Record<string, Session>, user: LoggedUser, template?: string, templates: Record<string, Template> | null, show: boolean, onCreate: (conf: SessionConfiguration, id?: string, ) => void, onHide: () => void , allowUserSelection?: boolean}): JSX.Element {
+export function SessionCreationDialog({ client, conf, user, template, templates, show, onCreate, onHide, allowUserSelection = false }: { client: Client, conf: Configuration, user: LoggedUser, template?: string, templates: Record<string, Template> | null, show: boolean, onCreate: (conf: SessionConfiguration, id?: string, ) => void, onHide: () => void , allowUserSelection?: boolean}): JSX.Element {
     const [selectedUser, setUser] = React.useState<string | null>(user.id);
     const [selectedTemplate, setTemplate] = React.useState<string | null>(null);
     const [duration, setDuration] = React.useState(conf.session.duration);
     const [poolAffinity, setPoolAffinity] = React.useState(conf.session.poolAffinity);
+    const [repository, setRepository] = React.useState("");
+    const [ref, setRef] = React.useState("");
     const [pools, setPools] = useState<Record<string, Pool> | null>(null);
     const [users, setUsers] = useState<Record<string, User> | null>(null);
 
@@ -114,6 +116,8 @@ export function SessionCreationDialog({ client, conf, sessions, user, template,
         setDuration(Number.isNaN(duration)? 0 : duration);
     };
     const handlePoolAffinityChange = (event: React.ChangeEvent<HTMLInputElement>) => setPoolAffinity(event.target.value);
+    const handleRepositoryChange = (event: React.ChangeEvent<HTMLInputElement>) => setRepository(event.target.value);
+    const handleRefChange = (event: React.ChangeEvent<HTMLInputElement>) => setRef(event.target.value);
 
     const currentUser = selectedUser || user.id;
     const currentTemplate = template || selectedTemplate;
@@ -131,9 +135,6 @@ export function SessionCreationDialog({ client, conf, sessions, user, template,
         if (!poolAffinity) {
             return false;
         }
-        if (sessions && sessions[currentUser] != null) {
-            return false;
-        }
         return true;
     }
 
@@ -196,8 +197,22 @@ export function SessionCreationDialog({ client, conf, sessions, user, template,
                         type="number"
                         label="Duration"
                         />}
+                    <TextField
+                        style={{marginBottom: 20}}
+                        value={repository}
+                        onChange={handleRepositoryChange}
+                        label="Repository"
+                        helperText="Overrides the template repository, e.g. a fork"
+                        />
+                    <TextField
+                        style={{marginBottom: 20}}
+                        value={ref}
+                        onChange={handleRefChange}
+                        label="Ref"
+                        helperText="Branch, tag or commit to check out"
+                        />
                     <ButtonGroup style={{alignSelf: "flex-end", marginTop: 20}} size="small">
-                        <Button disabled={!valid()} onClick={() => {onCreate({template: currentTemplate, duration: duration, poolAffinity: poolAffinity}, currentUser); onHide();}}>CREATE</Button>
+                        <Button disabled={!valid()} onClick={() => {onCreate({template: currentTemplate, duration: duration, poolAffinity: poolAffinity, repository: repository || undefined, ref: ref || undefined}, currentUser); onHide();}}>CREATE</Button>
                         <Button onClick={onHide}>CLOSE</Button>
                     </ButtonGroup>
                 </Container>
@@ -328,23 +343,24 @@ function Sessions({ client, conf, user }: { client: Client, conf: Configuration,
         setTemplates(templates);
     }, 5000);
 
-    function sessionMock(conf: SessionConfiguration): Session {
-        return {duration: conf.duration || 0, template: {name: "", image: "", description: ""}, userId: "", url: "", pod: {phase: 'Pending', reason: "", message: ""}};
+    function sessionMock(id: string, conf: SessionConfiguration): Session {
+        return {id: id, duration: conf.duration || 0, templateId: conf.template, templateVersion: conf.templateVersion || 0, template: {name: "", image: "", description: ""}, userId: user.id, url: "", poolAffinity: conf.poolAffinity || "", resources: {}, pod: {phase: 'Pending', reason: "", message: ""}};
     }
 
-    async function onCreate(conf: SessionConfiguration, id: string | null, setSessions: Dispatch<SetStateAction<Record<string, Session> | null>>): Promise<void> {
+    async function onCreate(conf: SessionConfiguration, setSessions: Dispatch<SetStateAction<Record<string, Session> | null>>): Promise<void> {
         try {
-            if (id) {
-                await client.createSession(id, conf);
-                setSessions((sessions: Record<string, Session> | null) => {
-                    if (sessions) {
-                        sessions[id] = sessionMock(conf);
-                    }
-                    return {...sessions};
-                });
-            } else {
-                await client.createCurrentSession(conf);
+            const admission = await client.createCurrentSession(conf);
+            if (admission.type == 'queued') {
+                setErrorMessage(`Session queued at position ${admission.position} on pool ${admission.poolAffinity}`);
+                return;
             }
+            const id = admission.id;
+            setSessions((sessions: Record<string, Session> | null) => {
+                if (sessions) {
+                    sessions[id] = sessionMock(id, conf);
+                }
+                return {...sessions};
+            });
         } catch (e) {
             console.error(e);
             setErrorMessage(`Failed to create session: ${e}`);
@@ -481,7 +497,7 @@ function Sessions({ client, conf, user }: { client: Client, conf: Configuration,
                         {errorMessage &&
                         <ErrorSnackbar open={true} message={errorMessage} onClose={() => setErrorMessage(null)} />}
                         {showCreationDialog &&
-                        <SessionCreationDialog allowUserSelection={true} client={client} conf={conf} sessions={resources} user={user} templates={templates} show={showCreationDialog} onCreate={(conf, id) => onCreate(conf, id, setSessions)} onHide={() => setShowCreationDialog(false)} />}
+                        <SessionCreationDialog client={client} conf={conf} user={user} templates={templates} show={showCreationDialog} onCreate={(conf) => onCreate(conf, setSessions)} onHide={() => setShowCreationDialog(false)} />}
                         {(selected && showUpdateDialog) &&
                         <SessionUpdateDialog id={selected} duration={resources[selected].duration} show={showUpdateDialog} onUpdate={(id, conf) => onUpdate(id, conf, setSessions)} onHide={() => setShowUpdateDialog(false)} />}
                     </>
@@ -969,6 +985,7 @@ function Pools({ client, user }: { client: Client, user: LoggedUser }): JSX.Elem
                                 <TableCell>Name</TableCell>
                                 <TableCell>Instance type</TableCell>
                                 <TableCell># of nodes</TableCell>
+                                <TableCell>Sessions</TableCell>
                             </TableRow>
                         </TableHead>
                         <TableBody>
@@ -977,6 +994,7 @@ function Pools({ client, user }: { client: Client, user: LoggedUser }): JSX.Elem
                             <TableCell>{pool.name}</TableCell>
                             <TableCell>{pool.instanceType}</TableCell>
                             <TableCell>{pool.nodes.length}</TableCell>
+                            <TableCell>{pool.used} / {pool.capacity}</TableCell>
                         </TableRow>
                         ))}
                         </TableBody>
@@ -25,6 +25,7 @@ import TableRow from '@material-ui/core/TableRow';
 import Typography from '@material-ui/core/Typography';
 import { makeStyles, createStyles, Theme } from '@material-ui/core/styles';
 import { Client, Configuration, NameValuePair, LoggedUser, Port, Session, SessionConfiguration, Template } from '@substrate/playground-client';
+import { currentSession } from '../utils';
 import { SessionCreationDialog, canCustomize } from "./admin";
 import { CenteredContainer, ErrorMessage, ErrorSnackbar, LoadingPanel } from "../components";
 import { useInterval } from "../hooks";
@@ -294,7 +295,7 @@ export function SessionDetails({ session }: {session: Session}): JSX.Element {
     );
 }
 
-function ExistingSession({session, onStop, onConnect}: {session: Session, onStop: () => void, onConnect: (session: Session) => void}): JSX.Element {
+function ExistingSession({session, onStop, onConnect}: {session: Session, onStop: (session: Session) => void, onConnect: (session: Session) => void}): JSX.Element {
     const [stopping, setStopping] = useState(false);
     const [errorMessage, setErrorMessage] = useState<string | null>(null);
 
@@ -309,7 +310,7 @@ function ExistingSession({session, onStop, onConnect}: {session: Session, onStop
     function onStopClick(): void {
         try {
             setStopping(true);
-            onStop();
+            onStop(session);
         } catch {
             setStopping(false);
             setErrorMessage("Failed to stop the session");
@@ -340,10 +341,10 @@ function ExistingSession({session, onStop, onConnect}: {session: Session, onStop
     );
 }
 
-export function SessionPanel({ client, conf, user, templates, onDeployed, onConnect, onRetry, onStop }: {client: Client, conf: Configuration, user: LoggedUser, templates: Record<string, Template>, onStop: () => void, onConnect: (session: Session) => void, onDeployed: (conf: SessionConfiguration) => Promise<void>, onRetry: () => void}): JSX.Element {
+export function SessionPanel({ client, conf, user, templates, onDeployed, onConnect, onRetry, onStop }: {client: Client, conf: Configuration, user: LoggedUser, templates: Record<string, Template>, onStop: (session: Session) => void, onConnect: (session: Session) => void, onDeployed: (conf: SessionConfiguration) => Promise<void>, onRetry: () => void}): JSX.Element {
     const [session, setSession] = useState<Session | null | undefined>(undefined);
 
-    useInterval(async () => setSession(await client.getCurrentSession()), 5000);
+    useInterval(async () => setSession(await currentSession(client)), 5000);
     return (
         <Container style={{ display: "flex", flex: 1, justifyContent: "center", alignItems: "center" }}>
             <Paper style={{ display: "flex", flexDirection: "column", height: "60vh", width: "60vw", justifyContent: "center"}} elevation={3}>
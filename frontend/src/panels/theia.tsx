@@ -2,7 +2,10 @@ import React, { useEffect, useRef, useState } from "react";
 import Paper from '@material-ui/core/Paper';
 import { Client, Template } from '@substrate/playground-client';
 import { CenteredContainer, ErrorMessage, Loading } from '../components';
-import { fetchWithTimeout } from '../utils';
+import { currentSession, fetchWithTimeout } from '../utils';
+
+// Delay between heartbeats reported while the session has focus
+const heartbeatPeriod = 60 * 1000;
 
 export function TheiaPanel({ client, autoDeploy, templates, onMissingSession, onSessionFailing, onSessionTimeout }: { client: Client, autoDeploy: string | null, templates: Record<string, Template>, onMissingSession: () => void, onSessionFailing: () => void, onSessionTimeout: () => void }): JSX.Element {
     const maxRetries = 5*60;
@@ -11,14 +14,14 @@ export function TheiaPanel({ client, autoDeploy, templates, onMissingSession, on
 
     useEffect(() => {
         async function fetchData() {
-            const session = await client.getCurrentSession();
+            const session = await currentSession(client);
             if (session) {
                 const phase = session.pod.phase;
                 if (phase == 'Running') {
                     // Check URL is fine
                     const url = `//${session.url}`;
                     if ((await fetchWithTimeout(url)).ok) {
-                        setData({ type: "SUCCESS", url: url });
+                        setData({ type: "SUCCESS", url: url, id: session.id });
                         return;
                     }
                 } else if (phase == 'Pending') {
@@ -46,7 +49,7 @@ export function TheiaPanel({ client, autoDeploy, templates, onMissingSession, on
                 }
 
                 try {
-                    client.getCurrentSession().then((session) => {
+                    currentSession(client).then((session) => {
                         if (session) {
                             setData({ type: "ERROR", value: "A session is still active", action: onMissingSession});
                         } else {
@@ -62,6 +65,19 @@ export function TheiaPanel({ client, autoDeploy, templates, onMissingSession, on
         }
     }, [data]);
 
+    useEffect(() => {
+        if (data.type != "SUCCESS") {
+            return;
+        }
+        // Focus stays in the document while the user interacts with the session iframe
+        const id = setInterval(() => {
+            if (document.hasFocus()) {
+                client.heartbeatSession(data.id).catch(e => console.error("Failed to report activity", e));
+            }
+        }, heartbeatPeriod);
+        return () => clearInterval(id);
+    }, [data]);
+
     if (data.type == "SUCCESS") {
         return <iframe ref={ref} src={data.url} frameBorder="0" width="100%" height="100%"></iframe>
     } else {
@@ -1,4 +1,4 @@
-import { LoggedUser } from "@substrate/playground-client";
+import { Client, LoggedUser, Session } from "@substrate/playground-client";
 
 function timeout<T>(promise: Promise<T>, ms: number): Promise<T> {
     return new Promise(function(resolve, reject) {
@@ -47,3 +47,11 @@ export function hasAdminReadRights(user: LoggedUser): boolean {
 export function hasAdminEditRights(user: LoggedUser): boolean {
     return user.admin;
 }
+
+// Session helpers
+
+// The UI focuses on a single session per user: the first one
+export async function currentSession(client: Client): Promise<Session | null> {
+    const sessions = await client.listCurrentSessions();
+    return sessions[0] || null;
+}